@@ -26,7 +26,7 @@ type Address = core::net::Ipv6Addr;
 pub struct AllocationRegistry(HashMap<Key, Value>);
 
 /// A key for an entry in the registry,
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Key {
     /// The protocol.
     pub protocol: Protocol,
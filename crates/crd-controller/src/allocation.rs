@@ -0,0 +1,330 @@
+//! External port allocation tracking.
+//!
+//! Detects the [`crd::PCPMap`]s and `LoadBalancer` [`Service`]s competing for the same
+//! external port before they race at the PCP server.
+
+use std::collections::HashMap;
+
+use allocation_registry::{AllocationRegistry, Entry, Key, Value};
+use k8s_openapi::api::core::v1::Service;
+use kube::runtime::reflector::ObjectRef;
+
+/// The holder of an external port.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Owner {
+    /// The [`crd::PCPMap`], holding a single external port.
+    Map(ObjectRef<crd::PCPMap>),
+
+    /// The mapping of a `LoadBalancer` [`Service`] port.
+    ///
+    /// The service holds an external port for each of its mappings.
+    Service(ObjectRef<Service>, pcp_client::mapping::Id),
+}
+
+impl Owner {
+    /// The namespace of the owning resource.
+    pub fn namespace(&self) -> Option<&str> {
+        match self {
+            Self::Map(obj) => obj.namespace.as_deref(),
+            Self::Service(obj, _) => obj.namespace.as_deref(),
+        }
+    }
+
+    /// The name of the owning resource.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Map(obj) => &obj.name,
+            Self::Service(obj, _) => &obj.name,
+        }
+    }
+}
+
+impl core::fmt::Display for Owner {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let kind = match self {
+            Self::Map(_) => "PCPMap",
+            Self::Service(..) => "Service",
+        };
+        write!(
+            f,
+            "{kind} {}/{}",
+            self.namespace().unwrap_or_default(),
+            self.name()
+        )
+    }
+}
+
+/// The resource claiming an external port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claimant {
+    /// The claiming resource.
+    pub owner: Owner,
+
+    /// The creation timestamp of the claiming resource.
+    pub created: Option<crd::Time>,
+
+    /// The PCP client mapping the claiming resource requests.
+    pub mapping_id: pcp_client::mapping::Id,
+}
+
+impl Claimant {
+    /// Create a new [`Claimant`] for the resource.
+    pub fn new(obj: &crd::PCPMap, mapping_id: pcp_client::mapping::Id) -> Self {
+        Self {
+            owner: Owner::Map(ObjectRef::from_obj(obj)),
+            created: obj.metadata.creation_timestamp.clone(),
+            mapping_id,
+        }
+    }
+
+    /// Create a new [`Claimant`] for the mapping of the service port.
+    pub fn service(svc: &Service, mapping_id: pcp_client::mapping::Id) -> Self {
+        Self {
+            owner: Owner::Service(ObjectRef::from_obj(svc), mapping_id),
+            created: svc.metadata.creation_timestamp.clone(),
+            mapping_id,
+        }
+    }
+
+    /// Check if this claimant takes precedence over the other one.
+    ///
+    /// The oldest resource wins, with the ties broken by the namespace and name.
+    /// The resources without the creation timestamp are considered the newest.
+    pub fn precedes(&self, other: &Self) -> bool {
+        let rank = |claimant: &Self| {
+            (
+                claimant.created.is_none(),
+                claimant.created.clone(),
+                claimant.owner.namespace().map(str::to_owned),
+                claimant.owner.name().to_owned(),
+            )
+        };
+        rank(self) < rank(other)
+    }
+}
+
+/// The outcome of the claim.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Claim {
+    /// The claim is granted.
+    Granted {
+        /// The previous holder of the external port that had to give way.
+        evicted: Option<Claimant>,
+    },
+
+    /// The external port is held by the claimant that takes precedence.
+    Rejected {
+        /// The current holder of the external port.
+        holder: Claimant,
+    },
+}
+
+/// The external port allocations of the resources.
+#[derive(Debug, Default)]
+pub struct Allocations {
+    /// The allocated ports.
+    registry: AllocationRegistry,
+
+    /// The holders of the allocated ports.
+    holders: HashMap<Key, (Claimant, Value)>,
+
+    /// The ports held by each resource.
+    keys: HashMap<Owner, Key>,
+}
+
+impl Allocations {
+    /// Claim the external port for the resource.
+    pub fn claim(&mut self, entry: Entry, claimant: Claimant) -> Claim {
+        // The resource has moved to another external port.
+        if self.keys.get(&claimant.owner) != Some(&entry.key) {
+            self.release(&claimant.owner);
+        }
+
+        let Entry { key, value } = entry;
+
+        let evicted = match self.holders.get(&key) {
+            None => {
+                if let Err(error) = self
+                    .registry
+                    .register(Entry::from_kv(key.clone(), value.clone()))
+                {
+                    tracing::warn!(message = "allocation registry is out of sync", %error);
+                }
+                None
+            }
+            Some((holder, _)) if holder.owner == claimant.owner => {
+                self.registry
+                    .force_register(Entry::from_kv(key.clone(), value.clone()));
+                None
+            }
+            Some((holder, _)) if !claimant.precedes(holder) => {
+                return Claim::Rejected {
+                    holder: holder.clone(),
+                };
+            }
+            Some((holder, _)) => {
+                let holder = holder.clone();
+                self.keys.remove(&holder.owner);
+                self.registry
+                    .force_register(Entry::from_kv(key.clone(), value.clone()));
+                Some(holder)
+            }
+        };
+
+        self.keys.insert(claimant.owner.clone(), key.clone());
+        self.holders.insert(key, (claimant, value));
+
+        Claim::Granted { evicted }
+    }
+
+    /// Release the external port held by the resource, if any.
+    pub fn release(&mut self, owner: &Owner) {
+        let Some(key) = self.keys.remove(owner) else {
+            return;
+        };
+        let Some((_, value)) = self.holders.remove(&key) else {
+            return;
+        };
+
+        if let Err(error) = self
+            .registry
+            .compare_and_unregister(Entry::from_kv(key, value))
+        {
+            tracing::warn!(message = "allocation registry is out of sync", %error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claimant(name: &str, created_secs: i64) -> Claimant {
+        let mut obj = crd::PCPMap::new(
+            name,
+            crd::PCPMapSpec {
+                protocol: crd::Protocol::Int(6),
+                from: Some(25565),
+                suggest_only: false,
+                to: "192.0.2.10:25565".parse().unwrap(),
+                allowed_sources: Vec::new(),
+                third_party: false,
+                gateway_ref: None,
+            },
+        );
+        obj.metadata.namespace = Some("default".to_owned());
+        obj.metadata.creation_timestamp =
+            Some(k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(
+                k8s_openapi::chrono::DateTime::from_timestamp(created_secs, 0).unwrap(),
+            ));
+
+        Claimant::new(
+            &obj,
+            pcp_client::mapping::Id {
+                protocol: 6,
+                internal_ip: std::net::Ipv6Addr::UNSPECIFIED,
+                internal_port: 25565,
+                nonce: [created_secs as u8; 12],
+            },
+        )
+    }
+
+    fn entry(internal_port: u16) -> Entry {
+        Entry::new(
+            6u8,
+            25565u16,
+            std::net::Ipv6Addr::UNSPECIFIED,
+            internal_port,
+        )
+    }
+
+    #[test]
+    fn oldest_wins() {
+        let older = claimant("older", 100);
+        let newer = claimant("newer", 200);
+
+        let mut allocations = Allocations::default();
+
+        assert_eq!(
+            allocations.claim(entry(1), newer.clone()),
+            Claim::Granted { evicted: None }
+        );
+        assert_eq!(
+            allocations.claim(entry(1), newer.clone()),
+            Claim::Granted { evicted: None }
+        );
+        assert_eq!(
+            allocations.claim(entry(2), older.clone()),
+            Claim::Granted {
+                evicted: Some(newer.clone())
+            }
+        );
+        assert_eq!(
+            allocations.claim(entry(1), newer.clone()),
+            Claim::Rejected {
+                holder: older.clone()
+            }
+        );
+
+        allocations.release(&older.owner);
+        assert_eq!(
+            allocations.claim(entry(1), newer),
+            Claim::Granted { evicted: None }
+        );
+    }
+
+    #[test]
+    fn service_ports() {
+        let mut svc = Service::default();
+        svc.metadata.name = Some("svc".to_owned());
+        svc.metadata.namespace = Some("default".to_owned());
+        svc.metadata.creation_timestamp =
+            Some(k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(
+                k8s_openapi::chrono::DateTime::from_timestamp(150, 0).unwrap(),
+            ));
+
+        let map = claimant("map", 100);
+        let port = |internal_port| {
+            Claimant::service(
+                &svc,
+                pcp_client::mapping::Id {
+                    internal_port,
+                    ..map.mapping_id
+                },
+            )
+        };
+        let other_entry = |internal_port: u16| {
+            Entry::new(
+                6u8,
+                25566u16,
+                std::net::Ipv6Addr::UNSPECIFIED,
+                internal_port,
+            )
+        };
+
+        let mut allocations = Allocations::default();
+
+        assert_eq!(
+            allocations.claim(entry(1), port(1)),
+            Claim::Granted { evicted: None }
+        );
+        // Every port of the service is held on its own.
+        assert_eq!(
+            allocations.claim(other_entry(2), port(2)),
+            Claim::Granted { evicted: None }
+        );
+        assert_eq!(
+            allocations.claim(entry(3), map.clone()),
+            Claim::Granted {
+                evicted: Some(port(1))
+            }
+        );
+        assert_eq!(
+            allocations.claim(entry(1), port(1)),
+            Claim::Rejected {
+                holder: map.clone()
+            }
+        );
+        assert_eq!(port(1).owner.to_string(), "Service default/svc".to_owned());
+    }
+}
@@ -0,0 +1,63 @@
+//! Kubernetes-style status conditions.
+
+use crd::{Condition, Time};
+
+/// The `True` condition status.
+pub const TRUE: &str = "True";
+
+/// The `False` condition status.
+pub const FALSE: &str = "False";
+
+/// Set the condition of the same type, or add it if there is none.
+///
+/// The last transition time is preserved if the condition status did not change.
+pub fn set(conditions: &mut Vec<Condition>, condition: Condition) {
+    let Some(existing) = conditions
+        .iter_mut()
+        .find(|existing| existing.type_ == condition.type_)
+    else {
+        conditions.push(condition);
+        return;
+    };
+
+    let last_transition_time = if existing.status == condition.status {
+        existing.last_transition_time.clone()
+    } else {
+        condition.last_transition_time
+    };
+
+    *existing = Condition {
+        last_transition_time,
+        ..condition
+    };
+}
+
+/// Create a new condition.
+pub fn new(
+    type_: &str,
+    status: bool,
+    reason: &str,
+    message: String,
+    observed_generation: Option<i64>,
+    now: &Time,
+) -> Condition {
+    Condition {
+        type_: type_.to_owned(),
+        status: if status { TRUE } else { FALSE }.to_owned(),
+        reason: reason.to_owned(),
+        message,
+        observed_generation,
+        last_transition_time: now.clone(),
+    }
+}
+
+/// How many times to write the conditions before giving up on the concurrent updates.
+///
+/// The conditions are patched as a whole, so the writes carry the resource version they
+/// were computed from, and are retried on the conflict.
+pub const WRITE_ATTEMPTS: usize = 5;
+
+/// Check if the write failed as the resource was updated concurrently.
+pub fn is_write_conflict(error: &kube::Error) -> bool {
+    matches!(error, kube::Error::Api(response) if response.code == 409)
+}
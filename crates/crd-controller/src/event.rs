@@ -0,0 +1,62 @@
+//! Kubernetes events about the mapping lifecycle.
+
+use kube::runtime::events;
+
+/// The event reasons.
+pub mod reason {
+    /// The mapping has been requested at the PCP server.
+    pub const REQUESTED: &str = "MappingRequested";
+
+    /// The PCP server has granted a different external port than requested.
+    pub const EXTERNAL_PORT_MISMATCH: &str = "ExternalPortMismatch";
+
+    /// The PCP server has rejected the request for a mapping that was granted before.
+    pub const RENEWAL_FAILED: &str = "RenewalFailed";
+
+    /// The PCP server has rejected the request for a new mapping.
+    pub const MAPPING_FAILED: &str = "MappingFailed";
+
+    /// The PCP server has lost its state, and the mapping is being requested again.
+    pub const SERVER_EPOCH_RESET: &str = "ServerEpochReset";
+
+    /// The external port is claimed by another resource, or not available at the server.
+    pub const EXTERNAL_PORT_CONFLICT: &str = "ExternalPortConflict";
+
+    /// The mapping has been removed from the PCP server.
+    pub const CLEANUP_COMPLETED: &str = "CleanupCompleted";
+}
+
+/// The event actions.
+pub mod action {
+    /// Requesting the mapping at the PCP server.
+    pub const MAP: &str = "Map";
+
+    /// Claiming the external port among the resources.
+    pub const CLAIM_EXTERNAL_PORT: &str = "ClaimExternalPort";
+
+    /// Removing the mapping from the PCP server.
+    pub const CLEANUP: &str = "Cleanup";
+}
+
+/// The name of the last PCP result code of the resource, for the event notes.
+pub fn result_code(status: Option<&crd::PCPMapStatus>) -> &str {
+    status
+        .and_then(|status| status.last_result_code.as_deref())
+        .unwrap_or("none")
+}
+
+/// Publish the event about the resource.
+///
+/// The events are informational, so the failures are logged rather than propagated.
+pub async fn publish(
+    client: &kube::Client,
+    reporter: &events::Reporter,
+    reference: k8s_openapi::api::core::v1::ObjectReference,
+    event: events::Event,
+) {
+    let reason = event.reason.clone();
+    let recorder = events::Recorder::new(client.clone(), reporter.clone(), reference);
+    if let Err(error) = recorder.publish(event).await {
+        tracing::warn!(message = "unable to publish the event", %reason, ?error);
+    }
+}
@@ -0,0 +1,391 @@
+//! Generation of the [`crd::PCPMap`]s for the annotated resources.
+//!
+//! The `Service`s and `Pod`s with the [`crd::annotation::EXPOSE`] annotation get the owned
+//! [`crd::PCPMap`]s generated for every port listed, so the deletion of the resource cascades
+//! to the mappings.
+
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+
+use futures::StreamExt as _;
+use k8s_openapi::api::core::v1::{Pod, Service};
+use kube::{runtime::controller::Action, ResourceExt as _};
+
+use crate::{pcp, reconciler};
+
+/// The field manager name for the generated resources.
+const FIELD_MANAGER: &str = "port-forward-controller";
+
+/// The context of the expose reconciler.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Context {
+    /// The execution params of the reconciler.
+    pub params: reconciler::Params,
+
+    /// The Kubernetes API client for controlled resources.
+    #[derivative(Debug = "ignore")]
+    pub k8s_client: kube::Client,
+
+    /// The address of the controller as the PCP client.
+    ///
+    /// The mappings to any other address are requested as the third party ones.
+    pub client_ip_address: Option<pcp_primitives::Address>,
+
+    /// The controller metrics.
+    #[derivative(Debug = "ignore")]
+    pub metrics: Arc<crate::metrics::Metrics>,
+
+    /// The leadership of this replica, the reconciles only run at the leader.
+    #[derivative(Debug = "ignore")]
+    pub leadership: leader_election::Leadership,
+}
+
+/// A single port listed at the [`crd::annotation::EXPOSE`] annotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    /// The protocol name, in lowercase.
+    pub protocol: String,
+
+    /// The port number.
+    pub number: crd::PortNumber,
+}
+
+/// The resource that can have its ports exposed.
+pub trait Source:
+    kube::Resource<DynamicType = (), Scope = kube::core::NamespaceResourceScope>
+    + Clone
+    + serde::de::DeserializeOwned
+    + core::fmt::Debug
+    + Send
+    + Sync
+    + 'static
+{
+    /// The name of the controller exposing the resources.
+    const CONTROLLER: &'static str;
+
+    /// The prefix of the generated [`crd::PCPMap`] names.
+    ///
+    /// Keeps the resources of different kinds with the same name from generating
+    /// the same [`crd::PCPMap`]s.
+    const NAME_PREFIX: &'static str;
+
+    /// Resolve the address to forward the exposed port to.
+    ///
+    /// Returns [`None`] if the port can not be resolved (yet).
+    fn resolve(&self, port: &Port) -> Option<SocketAddr>;
+}
+
+impl Source for Service {
+    const CONTROLLER: &'static str = "expose-service";
+    const NAME_PREFIX: &'static str = "svc";
+
+    /// The `ClusterIP` of the service at the same port.
+    fn resolve(&self, port: &Port) -> Option<SocketAddr> {
+        let spec = self.spec.as_ref()?;
+
+        let cluster_ip = spec.cluster_ip.as_deref()?.parse().ok()?;
+
+        spec.ports.iter().flatten().find(|service_port| {
+            service_port.port == i32::from(port.number)
+                && protocol_matches(service_port.protocol.as_deref(), &port.protocol)
+        })?;
+
+        Some(SocketAddr::new(cluster_ip, port.number))
+    }
+}
+
+impl Source for Pod {
+    const CONTROLLER: &'static str = "expose-pod";
+    const NAME_PREFIX: &'static str = "pod";
+
+    /// The host IP of the node the pod is running at, at the same `hostPort`.
+    fn resolve(&self, port: &Port) -> Option<SocketAddr> {
+        let host_ip = self.status.as_ref()?.host_ip.as_deref()?.parse().ok()?;
+
+        self.spec
+            .as_ref()?
+            .containers
+            .iter()
+            .flat_map(|container| container.ports.iter().flatten())
+            .find(|container_port| {
+                container_port.host_port == Some(i32::from(port.number))
+                    && protocol_matches(container_port.protocol.as_deref(), &port.protocol)
+            })?;
+
+        Some(SocketAddr::new(host_ip, port.number))
+    }
+}
+
+/// Check if the Kubernetes protocol name (`TCP` if unset) matches the protocol.
+fn protocol_matches(kubernetes_protocol: Option<&str>, protocol: &str) -> bool {
+    kubernetes_protocol
+        .unwrap_or("TCP")
+        .eq_ignore_ascii_case(protocol)
+}
+
+/// Parse the [`crd::annotation::EXPOSE`] annotation value.
+pub fn parse_annotation(value: &str) -> Result<Vec<Port>, Error> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let invalid = || Error::InvalidAnnotation(item.into());
+
+            let (protocol, number) = item.split_once('/').ok_or_else(invalid)?;
+            pcp::protocol_from_name(protocol).map_err(|_| invalid())?;
+            let number = number.parse().map_err(|_| invalid())?;
+
+            Ok(Port {
+                protocol: protocol.to_ascii_lowercase(),
+                number,
+            })
+        })
+        .collect()
+}
+
+/// Build the [`crd::PCPMap`]s to generate for the resource.
+pub fn desired_maps<K: Source>(
+    obj: &K,
+    client_ip_address: Option<pcp_primitives::Address>,
+) -> Result<Vec<crd::PCPMap>, Error> {
+    let Some(value) = obj.annotations().get(crd::annotation::EXPOSE) else {
+        return Ok(Vec::new());
+    };
+    let ports = parse_annotation(value)?;
+
+    let owner_ref = obj
+        .controller_owner_ref(&())
+        .ok_or(Error::MissingOwnerInfo)?;
+    let uid = owner_ref.uid.clone();
+
+    let maps = ports
+        .into_iter()
+        .filter_map(|port| {
+            let Some(to) = obj.resolve(&port) else {
+                tracing::debug!(message = "unable to resolve the exposed port", ?port);
+                return None;
+            };
+
+            let mut map = crd::PCPMap::new(
+                &format!(
+                    "{}-{}-{}-{}",
+                    K::NAME_PREFIX,
+                    obj.name_any(),
+                    port.protocol,
+                    port.number
+                ),
+                crd::PCPMapSpec {
+                    protocol: crd::Protocol::String(port.protocol),
+                    from: Some(port.number),
+                    suggest_only: false,
+                    to,
+                    allowed_sources: Vec::new(),
+                    third_party: Some(pcp_ip_conv::unify(to.ip())) != client_ip_address,
+                    gateway_ref: None,
+                },
+            );
+            map.metadata.namespace.clone_from(&obj.meta().namespace);
+            map.metadata.owner_references = Some(vec![owner_ref.clone()]);
+            map.metadata.labels = Some([(crd::label::OWNER_UID.to_owned(), uid.clone())].into());
+
+            Some(map)
+        })
+        .collect();
+
+    Ok(maps)
+}
+
+/// Bring the generated [`crd::PCPMap`]s in line with the annotation of the resource.
+pub async fn reconcile<K: Source>(obj: Arc<K>, ctx: Arc<Context>) -> Result<Action, Error> {
+    // The owned resources are removed by the garbage collector.
+    if obj.meta().deletion_timestamp.is_some() {
+        return Ok(Action::await_change());
+    }
+
+    let Some(uid) = obj.uid() else {
+        return Err(Error::MissingOwnerInfo);
+    };
+
+    let maps = desired_maps(&*obj, ctx.client_ip_address)?;
+
+    let api: kube::Api<crd::PCPMap> = match obj.namespace() {
+        Some(namespace) => kube::Api::namespaced(ctx.k8s_client.clone(), &namespace),
+        None => kube::Api::default_namespaced(ctx.k8s_client.clone()),
+    };
+
+    let patch_params = kube::api::PatchParams::apply(FIELD_MANAGER).force();
+    let mut desired_names = HashSet::new();
+    for map in maps {
+        let name = map.name_any();
+        api.patch(&name, &patch_params, &kube::api::Patch::Apply(&map))
+            .await?;
+        desired_names.insert(name);
+    }
+
+    let list_params =
+        kube::api::ListParams::default().labels(&format!("{}={uid}", crd::label::OWNER_UID));
+    for map in api.list(&list_params).await? {
+        let name = map.name_any();
+        if desired_names.contains(&name) {
+            continue;
+        }
+        api.delete(&name, &kube::api::DeleteParams::default())
+            .await?;
+    }
+
+    Ok(Action::await_change())
+}
+
+/// Apply the error.
+pub fn error_policy<K: Source>(_obj: Arc<K>, _error: &Error, ctx: Arc<Context>) -> Action {
+    Action::requeue(ctx.params.error_requeue_duration)
+}
+
+/// Run the controller until it exits.
+pub async fn run<K: Source>(controller: kube::runtime::Controller<K>, ctx: Arc<Context>) {
+    let pcp_maps_api = kube::Api::<crd::PCPMap>::all(ctx.k8s_client.clone());
+    let reconcile = crate::metrics::instrument(Arc::clone(&ctx.metrics), K::CONTROLLER, reconcile);
+    let reconcile = crate::leader::gate(ctx.leadership.clone(), reconcile);
+    controller
+        .reconcile_all_on(ctx.leadership.transitions())
+        .owns(pcp_maps_api, kube::runtime::watcher::Config::default())
+        .run(reconcile, error_policy, ctx)
+        .for_each(|result| async move {
+            match result {
+                Ok((obj, action)) => tracing::info!(message = "reconciled", ?obj, ?action),
+                Err(error) => tracing::error!(message = "reconcile failed", ?error),
+            }
+        })
+        .await;
+}
+
+/// An error that can occur while reconciling.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The annotation lists a port that is not in the `protocol/port` format.
+    #[error("invalid exposed port: {0}")]
+    InvalidAnnotation(Arc<str>),
+
+    /// The resource does not have the name or UID set.
+    #[error("the resource has no name or UID")]
+    MissingOwnerInfo,
+
+    /// A Kubernetes API call has failed.
+    #[error("Kubernetes API error: {0}")]
+    Kube(#[from] kube::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotation() {
+        assert_eq!(
+            parse_annotation("tcp/25565, UDP/25565,").unwrap(),
+            vec![
+                Port {
+                    protocol: "tcp".to_owned(),
+                    number: 25565
+                },
+                Port {
+                    protocol: "udp".to_owned(),
+                    number: 25565
+                },
+            ]
+        );
+        assert!(parse_annotation("tcp").is_err());
+        assert!(parse_annotation("tcp/65536").is_err());
+        assert!(parse_annotation("quic/443").is_err());
+    }
+
+    #[test]
+    fn service_maps() {
+        let svc = Service {
+            metadata: kube::core::ObjectMeta {
+                name: Some("minecraft".to_owned()),
+                namespace: Some("games".to_owned()),
+                uid: Some("1234".to_owned()),
+                annotations: Some(
+                    [(
+                        crd::annotation::EXPOSE.to_owned(),
+                        "tcp/25565,udp/25565".to_owned(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(k8s_openapi::api::core::v1::ServiceSpec {
+                cluster_ip: Some("10.96.0.10".to_owned()),
+                ports: Some(vec![k8s_openapi::api::core::v1::ServicePort {
+                    port: 25565,
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let maps = desired_maps(&svc, None).unwrap();
+        assert_eq!(maps.len(), 1);
+
+        let map = &maps[0];
+        assert_eq!(map.name_any(), "svc-minecraft-tcp-25565");
+        assert_eq!(map.namespace().as_deref(), Some("games"));
+        assert_eq!(map.spec.from, Some(25565));
+        assert_eq!(map.spec.to, "10.96.0.10:25565".parse().unwrap());
+        assert!(map.spec.third_party);
+        assert_eq!(map.owner_references()[0].uid, "1234");
+    }
+
+    #[test]
+    fn names_by_kind() {
+        let metadata = kube::core::ObjectMeta {
+            name: Some("minecraft".to_owned()),
+            namespace: Some("games".to_owned()),
+            uid: Some("1234".to_owned()),
+            annotations: Some(
+                [(crd::annotation::EXPOSE.to_owned(), "tcp/25565".to_owned())].into(),
+            ),
+            ..Default::default()
+        };
+
+        let svc = Service {
+            metadata: metadata.clone(),
+            spec: Some(k8s_openapi::api::core::v1::ServiceSpec {
+                cluster_ip: Some("10.96.0.10".to_owned()),
+                ports: Some(vec![k8s_openapi::api::core::v1::ServicePort {
+                    port: 25565,
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let pod = Pod {
+            metadata,
+            spec: Some(k8s_openapi::api::core::v1::PodSpec {
+                containers: vec![k8s_openapi::api::core::v1::Container {
+                    ports: Some(vec![k8s_openapi::api::core::v1::ContainerPort {
+                        container_port: 25565,
+                        host_port: Some(25565),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            status: Some(k8s_openapi::api::core::v1::PodStatus {
+                host_ip: Some("192.0.2.10".to_owned()),
+                ..Default::default()
+            }),
+        };
+
+        let svc_maps = desired_maps(&svc, None).unwrap();
+        let pod_maps = desired_maps(&pod, None).unwrap();
+        assert_eq!(svc_maps[0].name_any(), "svc-minecraft-tcp-25565");
+        assert_eq!(pod_maps[0].name_any(), "pod-minecraft-tcp-25565");
+    }
+}
@@ -0,0 +1,249 @@
+//! PCP gateways.
+//!
+//! Every gateway gets its own PCP client and [`allocation::Allocations`], as the ports
+//! are allocated independently at each PCP server.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use futures::{future::BoxFuture, StreamExt as _};
+use kube::{
+    runtime::{
+        controller::Action,
+        finalizer,
+        reflector::{ObjectRef, Store},
+    },
+    ResourceExt as _,
+};
+
+use crate::{allocation, reconciler};
+
+/// A PCP gateway the mappings are requested at.
+#[derive(Debug)]
+pub struct Gateway {
+    /// PCP client command channel sender.
+    pub command_tx: tokio::sync::mpsc::Sender<pcp_client::Command>,
+
+    /// The external ports claimed by the resources at this gateway.
+    pub allocations: Mutex<allocation::Allocations>,
+}
+
+impl Gateway {
+    /// Create a new [`Gateway`] for the PCP client.
+    pub fn new(command_tx: tokio::sync::mpsc::Sender<pcp_client::Command>) -> Self {
+        Self {
+            command_tx,
+            allocations: Default::default(),
+        }
+    }
+}
+
+/// The known PCP gateways.
+#[derive(Debug)]
+pub struct Gateways {
+    /// The gateway the controller is configured with.
+    default: Arc<Gateway>,
+
+    /// The gateways of the [`crd::PCPGateway`]s, by name.
+    named: RwLock<HashMap<String, (SocketAddr, Arc<Gateway>)>>,
+}
+
+impl Gateways {
+    /// Create new [`Gateways`] with just the default gateway.
+    pub fn new(default: Gateway) -> Self {
+        Self {
+            default: Arc::new(default),
+            named: Default::default(),
+        }
+    }
+
+    /// Get the gateway by the reference, the default one if [`None`].
+    pub fn get(&self, gateway_ref: Option<&str>) -> Option<Arc<Gateway>> {
+        let Some(name) = gateway_ref else {
+            return Some(self.default_gateway());
+        };
+        let named = self.named.read().unwrap();
+        named.get(name).map(|(_, gateway)| Arc::clone(gateway))
+    }
+
+    /// Get the default gateway.
+    pub fn default_gateway(&self) -> Arc<Gateway> {
+        Arc::clone(&self.default)
+    }
+
+    /// Check if the PCP client of the named gateway is running for the PCP server address.
+    ///
+    /// The clients stop once this replica is no longer the leader, and are to be started
+    /// again if it becomes the leader once more.
+    pub fn is_running(&self, name: &str, address: SocketAddr) -> bool {
+        let named = self.named.read().unwrap();
+        named.get(name).is_some_and(|(running_address, gateway)| {
+            *running_address == address && !gateway.command_tx.is_closed()
+        })
+    }
+
+    /// Add the named gateway, replacing the existing one.
+    ///
+    /// The PCP client of the replaced gateway stops the same way as on the removal.
+    pub fn insert(&self, name: String, address: SocketAddr, gateway: Gateway) {
+        let mut named = self.named.write().unwrap();
+        named.insert(name, (address, Arc::new(gateway)));
+    }
+
+    /// Remove the named gateway.
+    ///
+    /// The PCP client stops once the gateway is no longer in use, and deletes the mappings
+    /// it has requested.
+    pub fn remove(&self, name: &str) {
+        let mut named = self.named.write().unwrap();
+        named.remove(name);
+    }
+}
+
+/// Start a PCP client for the PCP server address, returning its command channel sender.
+pub type Spawner = Arc<
+    dyn Fn(
+            SocketAddr,
+        )
+            -> BoxFuture<'static, std::io::Result<tokio::sync::mpsc::Sender<pcp_client::Command>>>
+        + Send
+        + Sync,
+>;
+
+/// The context of the gateway reconciler.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Context {
+    /// The execution params of the reconciler.
+    pub params: reconciler::Params,
+
+    /// The known gateways.
+    pub gateways: Arc<Gateways>,
+
+    /// Starts the PCP clients for the gateways.
+    #[derivative(Debug = "ignore")]
+    pub spawner: Spawner,
+
+    /// The Kubernetes API client for controlled resources.
+    #[derivative(Debug = "ignore")]
+    pub k8s_client: kube::Client,
+
+    /// The [`crd::PCPMap`]s known to the [`crd::PCPMap`] controller.
+    #[derivative(Debug = "ignore")]
+    pub pcpmaps: Store<crd::PCPMap>,
+
+    /// Requeues the [`crd::PCPMap`]s at the [`crd::PCPMap`] controller.
+    pub pcpmap_requeue_tx: futures::channel::mpsc::UnboundedSender<ObjectRef<crd::PCPMap>>,
+
+    /// The controller metrics.
+    #[derivative(Debug = "ignore")]
+    pub metrics: Arc<crate::metrics::Metrics>,
+
+    /// The leadership of this replica, the reconciles only run at the leader.
+    #[derivative(Debug = "ignore")]
+    pub leadership: leader_election::Leadership,
+}
+
+/// Start the PCP client for the gateway, unless it is already running.
+pub async fn apply(obj: Arc<crd::PCPGateway>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let name = obj.name_any();
+    let address = obj.spec.address;
+
+    if ctx.gateways.is_running(&name, address) {
+        return Ok(Action::await_change());
+    }
+
+    let command_tx = (ctx.spawner)(address).await.map_err(Error::Spawn)?;
+    ctx.gateways
+        .insert(name.clone(), address, Gateway::new(command_tx));
+
+    tracing::info!(message = "PCP gateway started", gateway = ?name, %address);
+
+    requeue_mappings(&ctx, &name);
+
+    Ok(Action::await_change())
+}
+
+/// Stop the PCP client for the gateway.
+pub async fn cleanup(obj: Arc<crd::PCPGateway>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let name = obj.name_any();
+    ctx.gateways.remove(&name);
+
+    requeue_mappings(&ctx, &name);
+
+    Ok(Action::await_change())
+}
+
+/// Requeue the [`crd::PCPMap`]s at the gateway, so that they are requested at its current
+/// PCP client right away.
+fn requeue_mappings(ctx: &Context, name: &str) {
+    for obj in ctx.pcpmaps.state() {
+        let applied_gateway_ref = obj
+            .status
+            .as_ref()
+            .and_then(|status| status.applied_mapping.as_ref())
+            .and_then(|applied| applied.gateway_ref.as_deref());
+        if obj.spec.gateway_ref.as_deref() != Some(name) && applied_gateway_ref != Some(name) {
+            continue;
+        }
+
+        // Only fails if the controller is stopped.
+        let _ = ctx
+            .pcpmap_requeue_tx
+            .unbounded_send(ObjectRef::from_obj(&*obj));
+    }
+}
+
+/// Reconcile the changes at the API.
+pub async fn reconcile(
+    obj: Arc<crd::PCPGateway>,
+    ctx: Arc<Context>,
+) -> Result<Action, finalizer::Error<Error>> {
+    let api = kube::Api::<crd::PCPGateway>::all(ctx.k8s_client.clone());
+    finalizer(&api, &ctx.params.finalizer_name, obj, {
+        let ctx = Arc::clone(&ctx);
+        |event| async move {
+            match event {
+                finalizer::Event::Apply(obj) => apply(obj, ctx).await,
+                finalizer::Event::Cleanup(obj) => cleanup(obj, ctx).await,
+            }
+        }
+    })
+    .await
+}
+
+/// Apply the error.
+pub fn error_policy(
+    _obj: Arc<crd::PCPGateway>,
+    _error: &finalizer::Error<Error>,
+    ctx: Arc<Context>,
+) -> Action {
+    Action::requeue(ctx.params.error_requeue_duration)
+}
+
+/// Run the controller until it exits.
+pub async fn run(controller: kube::runtime::Controller<crd::PCPGateway>, ctx: Arc<Context>) {
+    let reconcile = crate::metrics::instrument(Arc::clone(&ctx.metrics), "pcpgateway", reconcile);
+    let reconcile = crate::leader::gate(ctx.leadership.clone(), reconcile);
+    controller
+        .reconcile_all_on(ctx.leadership.transitions())
+        .run(reconcile, error_policy, ctx)
+        .for_each(|result| async move {
+            match result {
+                Ok((obj, action)) => tracing::info!(message = "reconciled", ?obj, ?action),
+                Err(error) => tracing::error!(message = "reconcile failed", ?error),
+            }
+        })
+        .await;
+}
+
+/// An error that can occur while reconciling.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Starting the PCP client has failed.
+    #[error("unable to start the PCP client: {0}")]
+    Spawn(std::io::Error),
+}
@@ -0,0 +1,30 @@
+//! Running the reconcilers at the leader replica only.
+//!
+//! The controllers of the standby replicas keep watching the resources, so that their caches
+//! are warm by the time they take over, but skip the reconciles.
+
+use std::{future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use kube::runtime::controller::Action;
+use leader_election::Leadership;
+
+/// Wrap the reconcile fn to only run it at the leader.
+///
+/// The skipped reconciles are to be retriggered once the leadership is acquired,
+/// see [`Leadership::transitions`].
+pub fn gate<Object, Ctx, Reconcile, Fut, E>(
+    leadership: Leadership,
+    reconcile: Reconcile,
+) -> impl FnMut(Arc<Object>, Arc<Ctx>) -> BoxFuture<'static, Result<Action, E>>
+where
+    Reconcile: Fn(Arc<Object>, Arc<Ctx>) -> Fut,
+    Fut: Future<Output = Result<Action, E>> + Send + 'static,
+{
+    move |obj, ctx| {
+        if !leadership.is_leader() {
+            return Box::pin(async { Ok(Action::await_change()) });
+        }
+        Box::pin(reconcile(obj, ctx))
+    }
+}
@@ -1,7 +1,15 @@
 //! [`crd`] controller implementation.
 
+pub mod allocation;
+pub mod condition;
+pub mod event;
+pub mod expose;
+pub mod gateway;
+pub mod leader;
+pub mod metrics;
 pub mod pcp;
 pub mod reconciler;
+pub mod service;
 pub mod status;
 
 use std::sync::Arc;
@@ -13,8 +21,11 @@ pub async fn run(
     controller: kube::runtime::Controller<crd::PCPMap>,
     ctx: Arc<reconciler::Context>,
 ) {
+    let reconcile = metrics::instrument(Arc::clone(&ctx.metrics), "pcpmap", reconciler::reconcile);
+    let reconcile = leader::gate(ctx.leadership.clone(), reconcile);
     controller
-        .run(reconciler::reconcile, reconciler::error_policy, ctx)
+        .reconcile_all_on(ctx.leadership.transitions())
+        .run(reconcile, reconciler::error_policy, ctx)
         .for_each(|result| async move {
             match result {
                 Ok((obj, action)) => tracing::info!(message = "reconciled", ?obj, ?action),
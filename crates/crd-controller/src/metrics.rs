@@ -0,0 +1,111 @@
+//! The controller metrics.
+
+use std::{future::Future, sync::Arc, time::Instant};
+
+use futures::future::BoxFuture;
+use metrics::{Counter, Encoder, Family, Histogram, Type};
+
+/// The metrics of the controllers.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The durations of the reconciles, by controller.
+    pub reconcile_duration: Family<&'static str, Histogram>,
+
+    /// The failed reconciles, by controller.
+    pub reconcile_errors: Family<&'static str, Counter>,
+
+    /// The failed updates of the [`crd::PCPMap`] status by the [`crate::status::Listener`].
+    pub status_patch_failures: Counter,
+}
+
+impl Metrics {
+    /// Record the outcome of a reconcile of the controller.
+    pub fn record_reconcile(&self, controller: &'static str, started_at: Instant, succeeded: bool) {
+        let duration = started_at.elapsed().as_secs_f64();
+        self.reconcile_duration
+            .with(controller, |histogram| histogram.observe(duration));
+        self.reconcile_errors.with(controller, |counter| {
+            if !succeeded {
+                counter.inc();
+            }
+        });
+    }
+}
+
+impl metrics::Encode for Metrics {
+    fn encode(&self, encoder: &mut Encoder) {
+        let name = "controller_reconcile_duration_seconds";
+        encoder.header(name, "The durations of the reconciles.", Type::Histogram);
+        self.reconcile_duration.for_each(|controller, histogram| {
+            encoder.histogram(name, &[("controller", controller)], histogram)
+        });
+
+        let name = "controller_reconcile_errors_total";
+        encoder.header(name, "The failed reconciles.", Type::Counter);
+        self.reconcile_errors.for_each(|controller, counter| {
+            encoder.sample(name, &[("controller", controller)], counter.get())
+        });
+
+        let name = "controller_status_patch_failures_total";
+        encoder.header(
+            name,
+            "The failed updates of the PCPMap status.",
+            Type::Counter,
+        );
+        encoder.sample(name, &[], self.status_patch_failures.get());
+    }
+}
+
+/// Wrap the reconcile fn to record its outcomes as the given controller.
+pub fn instrument<Object, Ctx, Reconcile, Fut, T, E>(
+    metrics: Arc<Metrics>,
+    controller: &'static str,
+    reconcile: Reconcile,
+) -> impl Fn(Arc<Object>, Arc<Ctx>) -> BoxFuture<'static, Result<T, E>>
+where
+    Reconcile: Fn(Arc<Object>, Arc<Ctx>) -> Fut,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+{
+    move |obj, ctx| {
+        let metrics = Arc::clone(&metrics);
+        let fut = reconcile(obj, ctx);
+        Box::pin(async move {
+            let started_at = Instant::now();
+            let result = fut.await;
+            metrics.record_reconcile(controller, started_at, result.is_ok());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instrumented_reconcile() {
+        let metrics = Arc::new(Metrics::default());
+
+        let reconcile = instrument(
+            Arc::clone(&metrics),
+            "test",
+            |obj: Arc<bool>, _ctx: Arc<()>| async move {
+                if *obj {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            },
+        );
+
+        futures::executor::block_on(reconcile(Arc::new(true), Arc::new(()))).unwrap();
+        futures::executor::block_on(reconcile(Arc::new(false), Arc::new(()))).unwrap_err();
+
+        metrics
+            .reconcile_duration
+            .with("test", |histogram| assert_eq!(histogram.count(), 2));
+        metrics
+            .reconcile_errors
+            .with("test", |counter| assert_eq!(counter.get(), 1));
+    }
+}
@@ -14,16 +14,38 @@ pub enum ConversionError {
     /// The protocol is a string that we don't recognize.
     #[error("unknown protocol name: {0}")]
     UnknownProtocolName(Arc<str>),
+
+    /// The allowed source is not a valid CIDR.
+    #[error("invalid allowed source: {0}")]
+    InvalidAllowedSource(Arc<str>),
+
+    /// The nonce annotation is not set yet.
+    #[error("the nonce annotation is missing")]
+    MissingNonce,
+
+    /// The nonce annotation is not a valid nonce.
+    #[error("invalid nonce: {0}")]
+    InvalidNonce(Arc<str>),
+
+    /// The port number is out of range.
+    #[error("invalid port number: {0}")]
+    InvalidPortNumber(std::num::TryFromIntError),
+
+    /// The mapping targets the controller itself, but the controller address is not known.
+    #[error("the controller address is required for this mapping")]
+    NoClientAddress,
 }
 
 /// Convert the CRD into a PCP type.
 #[derive(Debug, Clone)]
 pub struct Converter {
-    /// The nonce to use for all the managed mappings.
-    pub nonce: pcp_primitives::Nonce,
-
     /// The lifetime to request for the mappings.
     pub lifetime: pcp_primitives::LifetimeSeconds,
+
+    /// The address of the controller as the PCP client.
+    ///
+    /// Required to request the third party mappings and the mappings for the services.
+    pub client_ip_address: Option<pcp_primitives::Address>,
 }
 
 impl Converter {
@@ -46,27 +68,33 @@ impl Converter {
         let crd::PCPMapSpec {
             protocol,
             from: _,
+            suggest_only: _,
             to,
+            allowed_sources: _,
+            third_party,
+            gateway_ref: _,
         } = &crd.spec;
 
         let protocol = match protocol {
             IntOrString::Int(val) => pcp_primitives::Protocol::try_from(*val)
                 .map_err(ConversionError::InvalidProtocolNumber)?,
-            IntOrString::String(val) => match val.to_ascii_lowercase().as_str() {
-                "any" => pcp_consts::protocol::ANY,
-                "tcp" => pcp_consts::protocol::TCP,
-                "udp" => pcp_consts::protocol::UDP,
-                "sctp" => pcp_consts::protocol::SCTP,
-                "dccp" => pcp_consts::protocol::DCCP,
-                _ => return Err(ConversionError::UnknownProtocolName(val.to_owned().into())),
-            },
+            IntOrString::String(val) => protocol_from_name(val)?,
+        };
+
+        // The third party mappings are requested from the controller address, and the server
+        // responds to it as well.
+        let internal_ip = if *third_party {
+            self.client_ip_address
+                .ok_or(ConversionError::NoClientAddress)?
+        } else {
+            pcp_ip_conv::unify(to.ip())
         };
 
         Ok(pcp_client::mapping::Id {
             protocol,
-            internal_ip: pcp_ip_conv::unify(to.ip()),
+            internal_ip,
             internal_port: to.port(),
-            nonce: self.nonce,
+            nonce: nonce_from_crd(crd)?,
         })
     }
 
@@ -78,19 +106,261 @@ impl Converter {
         let crd::PCPMapSpec {
             protocol: _,
             from,
-            to: _,
+            suggest_only,
+            to,
+            allowed_sources,
+            third_party,
+            gateway_ref: _,
         } = &crd.spec;
 
+        let third_party = third_party.then(|| pcp_client::mapping::option::PcpOption {
+            is_optional: false,
+            payload: pcp_ip_conv::unify(to.ip()),
+        });
+
+        let filters = if allowed_sources.is_empty() {
+            None
+        } else {
+            let filters = allowed_sources
+                .iter()
+                .map(|source| parse_cidr(source))
+                .collect::<Result<_, _>>()?;
+            Some(pcp_client::mapping::option::PcpOption {
+                is_optional: false,
+                payload: filters,
+            })
+        };
+
         Ok(pcp_client::mapping::Params {
             lifetime: self.lifetime,
-            external_port: *from,
-            exteranl_ip: pcp_primitives::Address::UNSPECIFIED, // TODO: use the value from status if present
-            third_party: None,
-            prefer_failure: Some(pcp_client::mapping::option::PcpOption {
-                is_optional: false,
-                payload: (),
-            }),
-            filters: None,
+            external_port: from.unwrap_or(pcp_consts::port::ANY),
+            // The reconciler suggests the endpoint from the status, see
+            // [`crate::reconciler::Applied::suggested_external`].
+            exteranl_ip: pcp_primitives::Address::UNSPECIFIED,
+            third_party,
+            // Without the exact port to insist on, any assigned one would do.
+            prefer_failure: (from.is_some() && !suggest_only).then_some(
+                pcp_client::mapping::option::PcpOption {
+                    is_optional: false,
+                    payload: (),
+                },
+            ),
+            filters,
         })
     }
 }
+
+/// Describe the applied mapping for the status.
+pub fn applied_mapping_to_crd(
+    id: &pcp_client::mapping::Id,
+    gateway_ref: Option<String>,
+    generation: Option<i64>,
+) -> crd::AppliedMapping {
+    crd::AppliedMapping {
+        protocol_number: id.protocol,
+        internal_ip: pcp_ip_conv::split(id.internal_ip),
+        internal_port: id.internal_port,
+        gateway_ref,
+        generation,
+    }
+}
+
+/// Restore the ID of the applied mapping recorded at the status.
+pub fn applied_mapping_id_from_crd(
+    crd: &crd::PCPMap,
+    applied: &crd::AppliedMapping,
+) -> Result<pcp_client::mapping::Id, ConversionError> {
+    Ok(pcp_client::mapping::Id {
+        protocol: applied.protocol_number,
+        internal_ip: pcp_ip_conv::unify(applied.internal_ip),
+        internal_port: applied.internal_port,
+        nonce: nonce_from_crd(crd)?,
+    })
+}
+
+/// Parse the protocol name.
+pub fn protocol_from_name(name: &str) -> Result<pcp_primitives::Protocol, ConversionError> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "any" => pcp_consts::protocol::ANY,
+        "tcp" => pcp_consts::protocol::TCP,
+        "udp" => pcp_consts::protocol::UDP,
+        "sctp" => pcp_consts::protocol::SCTP,
+        "dccp" => pcp_consts::protocol::DCCP,
+        _ => return Err(ConversionError::UnknownProtocolName(name.into())),
+    })
+}
+
+/// Read the mapping nonce from the CRD annotation.
+pub fn nonce_from_crd(crd: &crd::PCPMap) -> Result<pcp_primitives::Nonce, ConversionError> {
+    nonce_from_meta(&crd.metadata)
+}
+
+/// Read the mapping nonce from the annotation of any resource.
+pub fn nonce_from_meta(
+    meta: &k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta,
+) -> Result<pcp_primitives::Nonce, ConversionError> {
+    let value = meta
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(crd::annotation::NONCE))
+        .ok_or(ConversionError::MissingNonce)?;
+
+    parse_nonce(value).ok_or_else(|| ConversionError::InvalidNonce(value.as_str().into()))
+}
+
+/// Generate a new random mapping nonce.
+///
+/// See <https://datatracker.ietf.org/doc/html/rfc6887#section-11.1>.
+pub fn generate_nonce() -> pcp_primitives::Nonce {
+    use rand::RngCore as _;
+
+    let mut nonce = pcp_primitives::Nonce::default();
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Format the nonce as a hex string.
+pub fn format_nonce(nonce: &pcp_primitives::Nonce) -> String {
+    use std::fmt::Write as _;
+
+    nonce.iter().fold(String::new(), |mut output, byte| {
+        let _ = write!(output, "{byte:02x}");
+        output
+    })
+}
+
+/// Parse the nonce from a hex string.
+fn parse_nonce(value: &str) -> Option<pcp_primitives::Nonce> {
+    if !value.bytes().all(|byte| byte.is_ascii_hexdigit())
+        || value.len() != core::mem::size_of::<pcp_primitives::Nonce>() * 2
+    {
+        return None;
+    }
+
+    let mut nonce = pcp_primitives::Nonce::default();
+    for (byte, hex) in nonce.iter_mut().zip(value.as_bytes().chunks_exact(2)) {
+        let hex = core::str::from_utf8(hex).ok()?;
+        *byte = u8::from_str_radix(hex, 16).ok()?;
+    }
+    Some(nonce)
+}
+
+/// Parse the CIDR into the PCP filter.
+///
+/// The IPv4 prefixes are converted to the IPv4-mapped IPv6 ones.
+pub fn parse_cidr(cidr: &str) -> Result<pcp_client::mapping::option::Filter, ConversionError> {
+    let invalid = || ConversionError::InvalidAllowedSource(cidr.into());
+
+    let (address, prefix_length) = cidr.split_once('/').ok_or_else(invalid)?;
+    let address: std::net::IpAddr = address.parse().map_err(|_| invalid())?;
+    let prefix_length: pcp_primitives::PrefixLength =
+        prefix_length.parse().map_err(|_| invalid())?;
+
+    let prefix_length = match address {
+        std::net::IpAddr::V4(_) if prefix_length <= 32 => prefix_length + 96,
+        std::net::IpAddr::V6(_) if prefix_length <= 128 => prefix_length,
+        _ => return Err(invalid()),
+    };
+
+    Ok((pcp_ip_conv::unify(address), prefix_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_roundtrip() {
+        let nonce = generate_nonce();
+        let formatted = format_nonce(&nonce);
+        assert_eq!(formatted.len(), 24);
+        assert_eq!(parse_nonce(&formatted), Some(nonce));
+
+        assert_eq!(parse_nonce("0102030405060708090a0b"), None);
+        assert_eq!(parse_nonce("+102030405060708090a0b0c"), None);
+    }
+
+    #[test]
+    fn applied_mapping_roundtrip() {
+        let nonce = generate_nonce();
+        let mut crd = crd::PCPMap::new(
+            "test",
+            crd::PCPMapSpec {
+                protocol: IntOrString::String("tcp".to_owned()),
+                from: Some(80),
+                suggest_only: false,
+                to: "192.0.2.10:8080".parse().unwrap(),
+                allowed_sources: Vec::new(),
+                third_party: false,
+                gateway_ref: None,
+            },
+        );
+        crd.metadata.annotations = Some(
+            [(crd::annotation::NONCE.to_owned(), format_nonce(&nonce))]
+                .into_iter()
+                .collect(),
+        );
+
+        let converter = Converter {
+            lifetime: 120,
+            client_ip_address: None,
+        };
+        let id = converter.mapping_id_from_crd(&crd).unwrap();
+
+        let applied = applied_mapping_to_crd(&id, None, Some(3));
+        assert_eq!(applied.internal_ip, std::net::IpAddr::from([192, 0, 2, 10]));
+        assert_eq!(applied_mapping_id_from_crd(&crd, &applied).unwrap(), id);
+    }
+
+    #[test]
+    fn external_port() {
+        let converter = Converter {
+            lifetime: 120,
+            client_ip_address: None,
+        };
+        let params = |from, suggest_only| {
+            let crd = crd::PCPMap::new(
+                "test",
+                crd::PCPMapSpec {
+                    protocol: IntOrString::String("udp".to_owned()),
+                    from,
+                    suggest_only,
+                    to: "192.0.2.10:27015".parse().unwrap(),
+                    allowed_sources: Vec::new(),
+                    third_party: false,
+                    gateway_ref: None,
+                },
+            );
+            converter.mapping_params_from_crd(&crd).unwrap()
+        };
+
+        let exact = params(Some(27015), false);
+        assert_eq!(exact.external_port, 27015);
+        assert!(exact.prefer_failure.is_some());
+
+        let suggested = params(Some(27015), true);
+        assert_eq!(suggested.external_port, 27015);
+        assert!(suggested.prefer_failure.is_none());
+
+        let any = params(None, false);
+        assert_eq!(any.external_port, pcp_consts::port::ANY);
+        assert!(any.prefer_failure.is_none());
+    }
+
+    #[test]
+    fn cidr() {
+        assert_eq!(
+            parse_cidr("203.0.113.0/24").unwrap(),
+            (
+                std::net::Ipv4Addr::new(203, 0, 113, 0).to_ipv6_mapped(),
+                120
+            )
+        );
+        assert_eq!(
+            parse_cidr("2001:db8::/32").unwrap(),
+            ("2001:db8::".parse().unwrap(), 32)
+        );
+        assert!(parse_cidr("203.0.113.0/33").is_err());
+        assert!(parse_cidr("203.0.113.0").is_err());
+    }
+}
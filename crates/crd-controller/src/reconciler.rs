@@ -1,10 +1,16 @@
 //! Reconciler.
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use kube::runtime::{controller::Action, finalizer};
+use kube::{
+    runtime::{controller::Action, events, finalizer, reflector::ObjectRef},
+    Resource as _,
+};
 
-use crate::pcp;
+use crate::{allocation, condition, event, gateway, pcp};
 
 /// The name of the finalizer.
 const DEFAULT_FINALIZER_NAME: &str = "port-forward-controller.io/cleanup";
@@ -28,6 +34,10 @@ pub struct Params {
 
     /// The duration after which to requeue the resource after an error during reconciliation.
     pub error_requeue_duration: std::time::Duration,
+
+    /// The duration after which to requeue the resource to check on the mappings that are
+    /// not effective yet.
+    pub pending_requeue_duration: std::time::Duration,
 }
 
 impl Default for Params {
@@ -37,6 +47,7 @@ impl Default for Params {
             pcp_client_command_timeout: std::time::Duration::from_secs(60),
             cleanup_requeue_duration: std::time::Duration::from_secs(10),
             error_requeue_duration: std::time::Duration::from_secs(60),
+            pending_requeue_duration: std::time::Duration::from_secs(5),
         }
     }
 }
@@ -48,8 +59,8 @@ pub struct Context {
     /// The execution params of the reconciler.
     pub params: Params,
 
-    /// PCP client command channel sender.
-    pub command_tx: tokio::sync::mpsc::Sender<pcp_client::Command>,
+    /// The gateways to request the mappings at.
+    pub gateways: Arc<gateway::Gateways>,
 
     /// The Kubernetes API client for controlled resources.
     #[derivative(Debug = "ignore")]
@@ -57,32 +68,330 @@ pub struct Context {
 
     /// The converter for the CRD and PCP types.
     pub converter: pcp::Converter,
+
+    /// The reporter of the events.
+    pub reporter: events::Reporter,
+
+    /// The gateway reference and the mapping that were applied for each resource.
+    ///
+    /// Used to remove the mapping from the previous gateway when the resource moves.
+    pub applied: Mutex<HashMap<ObjectRef<crd::PCPMap>, Applied>>,
+
+    /// The controller metrics.
+    #[derivative(Debug = "ignore")]
+    pub metrics: Arc<crate::metrics::Metrics>,
+
+    /// The leadership of this replica, the reconciles only run at the leader.
+    #[derivative(Debug = "ignore")]
+    pub leadership: leader_election::Leadership,
+}
+
+/// The mapping applied for a resource.
+#[derive(Debug, Clone)]
+pub struct Applied {
+    /// The gateway the mapping was requested at.
+    pub gateway_ref: Option<String>,
+
+    /// The mapping ID.
+    pub mapping_id: pcp_client::mapping::Id,
+
+    /// The generation of the resource the mapping was applied for.
+    pub generation: Option<i64>,
+
+    /// The external endpoint to suggest to the PCP server, restored from the status.
+    ///
+    /// The PCP client only suggests the endpoints it was granted itself, so this keeps
+    /// the external endpoint stable across the controller restarts.
+    pub suggested_external: Option<std::net::SocketAddr>,
+}
+
+impl Context {
+    /// Get the gateway by the reference.
+    fn gateway(&self, gateway_ref: Option<&str>) -> Result<Arc<gateway::Gateway>, Error> {
+        self.gateways
+            .get(gateway_ref)
+            .ok_or_else(|| Error::UnknownGateway(gateway_ref.unwrap_or_default().into()))
+    }
+}
+
+/// Make sure the resource has the nonce assigned, generating and persisting a new one
+/// if needed.
+///
+/// Returns the up-to-date resource.
+pub(crate) async fn ensure_nonce<K>(obj: Arc<K>, client: &kube::Client) -> Result<Arc<K>, Error>
+where
+    K: kube::Resource<DynamicType = (), Scope = kube::core::NamespaceResourceScope>
+        + Clone
+        + serde::de::DeserializeOwned
+        + core::fmt::Debug,
+{
+    match pcp::nonce_from_meta(obj.meta()) {
+        Ok(_) => return Ok(obj),
+        Err(pcp::ConversionError::MissingNonce) => {}
+        Err(error) => return Err(Error::Converter(error)),
+    }
+
+    let nonce = pcp::format_nonce(&pcp::generate_nonce());
+
+    let patch = kube::api::Patch::Merge(serde_json::json!({
+        "metadata": {
+            "annotations": {
+                crd::annotation::NONCE: nonce,
+            },
+        },
+    }));
+
+    let api = api_for(&ObjectRef::from_obj(&*obj), client);
+    let name = obj.meta().name.as_deref().unwrap_or_default();
+    let obj = api
+        .patch(name, &kube::api::PatchParams::default(), &patch)
+        .await
+        .map_err(Error::Kube)?;
+
+    Ok(Arc::new(obj))
+}
+
+/// Get the API for the namespace of the given resource.
+pub(crate) fn api_for<K>(obj: &ObjectRef<K>, client: &kube::Client) -> kube::Api<K>
+where
+    K: kube::Resource<DynamicType = (), Scope = kube::core::NamespaceResourceScope>,
+{
+    let client = client.clone();
+    match obj.namespace.as_deref() {
+        Some(namespace) => kube::Api::namespaced(client, namespace),
+        None => kube::Api::default_namespaced(client),
+    }
 }
 
 /// Apply the mapping update that happened at the API to the PCP client state.
 pub async fn apply(obj: Arc<crd::PCPMap>, ctx: Arc<Context>) -> Result<Action, Error> {
-    let mapping = ctx
+    let obj = ensure_nonce(obj, &ctx.k8s_client).await?;
+
+    let mut mapping = ctx
         .converter
         .mapping_from_crd(&obj)
         .map_err(Error::Converter)?;
-    ctx.command_tx
+
+    let obj_ref = ObjectRef::from_obj(&*obj);
+    let owner = allocation::Owner::Map(obj_ref.clone());
+    let gateway_ref = obj.spec.gateway_ref.clone();
+    let gateway = ctx.gateway(gateway_ref.as_deref())?;
+
+    let prev = ctx.applied.lock().unwrap().get(&obj_ref).cloned();
+    // The controller might have restarted since the mapping was applied.
+    let prev = match prev {
+        Some(prev) => Some(prev),
+        None => applied_from_status(&obj)?,
+    };
+    let requested = prev.as_ref().map_or(true, |prev| {
+        prev.gateway_ref != gateway_ref || prev.mapping_id != mapping.id
+    });
+    let suggested_external = match prev.as_ref().filter(|_| !requested) {
+        // The endpoint was granted for the previous spec, and might not satisfy the current one.
+        Some(prev) if prev.generation == obj.metadata.generation => prev.suggested_external,
+        _ => None,
+    };
+    if let Some(Applied {
+        gateway_ref: prev_gateway_ref,
+        mapping_id: prev_id,
+        ..
+    }) = prev.filter(|_| requested)
+    {
+        // The previous gateway might be gone already, and the mapping with it.
+        if let Some(prev_gateway) = ctx.gateways.get(prev_gateway_ref.as_deref()) {
+            if prev_gateway_ref != gateway_ref {
+                prev_gateway.allocations.lock().unwrap().release(&owner);
+            }
+            // The changes to the spec have made it a different mapping, so the previous one
+            // would leak otherwise.
+            prev_gateway
+                .command_tx
+                .send_timeout(
+                    pcp_client::Command::RemoveDesired(prev_id),
+                    ctx.params.pcp_client_command_timeout,
+                )
+                .await?;
+        }
+    }
+
+    let id = mapping.id;
+    let claimant = allocation::Claimant::new(&obj, id);
+    // Only the exact ports are competed for, the server assigns the others freely.
+    let claim = match obj.spec.from.filter(|_| !obj.spec.suggest_only) {
+        Some(from) => {
+            let entry = allocation_registry::Entry::new(
+                mapping.id.protocol,
+                from,
+                mapping.id.internal_ip,
+                mapping.id.internal_port,
+            );
+            gateway
+                .allocations
+                .lock()
+                .unwrap()
+                .claim(entry, claimant.clone())
+        }
+        None => {
+            gateway.allocations.lock().unwrap().release(&owner);
+            allocation::Claim::Granted { evicted: None }
+        }
+    };
+
+    match claim {
+        allocation::Claim::Granted { evicted: None } => {}
+        allocation::Claim::Granted {
+            evicted: Some(evicted),
+        } => {
+            gateway
+                .command_tx
+                .send_timeout(
+                    pcp_client::Command::RemoveDesired(evicted.mapping_id),
+                    ctx.params.pcp_client_command_timeout,
+                )
+                .await?;
+            report_conflict(&ctx.k8s_client, &ctx.reporter, &evicted.owner, &claimant).await?;
+        }
+        allocation::Claim::Rejected { holder } => {
+            gateway
+                .command_tx
+                .send_timeout(
+                    pcp_client::Command::RemoveDesired(mapping.id),
+                    ctx.params.pcp_client_command_timeout,
+                )
+                .await?;
+            report_conflict(&ctx.k8s_client, &ctx.reporter, &owner, &holder).await?;
+
+            // Retry in case the holder goes away.
+            return Ok(Action::requeue(ctx.params.error_requeue_duration));
+        }
+    }
+
+    if let Some(external) = suggested_external {
+        mapping.params.external_port = external.port();
+        mapping.params.exteranl_ip = pcp_ip_conv::unify(external.ip());
+    }
+
+    gateway
+        .command_tx
         .send_timeout(
             pcp_client::Command::UpsertDesired(mapping),
             ctx.params.pcp_client_command_timeout,
         )
         .await?;
 
+    if requested {
+        let port = obj.spec.from.map_or_else(
+            || "any external port".to_owned(),
+            |from| format!("the external port {from}"),
+        );
+        let note = format!(
+            "requested {port} at the {} gateway, last result code {}",
+            gateway_ref.as_deref().unwrap_or("default"),
+            event::result_code(obj.status.as_ref()),
+        );
+        event::publish(
+            &ctx.k8s_client,
+            &ctx.reporter,
+            obj.object_ref(&()),
+            events::Event {
+                type_: events::EventType::Normal,
+                reason: event::reason::REQUESTED.to_owned(),
+                note: Some(note),
+                action: event::action::MAP.to_owned(),
+                secondary: None,
+            },
+        )
+        .await;
+    }
+
+    ctx.applied.lock().unwrap().insert(
+        obj_ref.clone(),
+        Applied {
+            gateway_ref: gateway_ref.clone(),
+            mapping_id: id,
+            generation: obj.metadata.generation,
+            suggested_external,
+        },
+    );
+
+    // The status listener reports the generation as observed once this mapping is effective.
+    let applied_mapping = pcp::applied_mapping_to_crd(&id, gateway_ref, obj.metadata.generation);
+    let status_applied_mapping = obj
+        .status
+        .as_ref()
+        .and_then(|status| status.applied_mapping.as_ref());
+    if status_applied_mapping != Some(&applied_mapping) {
+        let patch = kube::api::Patch::Merge(serde_json::json!({
+            "status": {
+                "appliedMapping": applied_mapping,
+            },
+        }));
+        let name = obj.metadata.name.as_deref().unwrap_or_default();
+        api_for(&obj_ref, &ctx.k8s_client)
+            .patch_status(name, &kube::api::PatchParams::default(), &patch)
+            .await
+            .map_err(Error::Kube)?;
+    }
+
     Ok(Action::requeue(std::time::Duration::from_secs(60)))
 }
 
+/// Restore the mapping applied for the resource from its status.
+fn applied_from_status(obj: &crd::PCPMap) -> Result<Option<Applied>, Error> {
+    let Some(applied) = obj
+        .status
+        .as_ref()
+        .and_then(|status| status.applied_mapping.as_ref())
+    else {
+        return Ok(None);
+    };
+
+    let mapping_id = pcp::applied_mapping_id_from_crd(obj, applied).map_err(Error::Converter)?;
+
+    Ok(Some(Applied {
+        gateway_ref: applied.gateway_ref.clone(),
+        mapping_id,
+        generation: applied.generation,
+        suggested_external: obj
+            .status
+            .as_ref()
+            .and_then(|status| status.external_endpoint),
+    }))
+}
+
 /// Run the cleanup process from the mapping at the PCP client in response to the resource
 /// deletion at the API.
 pub async fn cleanup(obj: Arc<crd::PCPMap>, ctx: Arc<Context>) -> Result<Action, Error> {
-    let id = ctx
-        .converter
-        .mapping_id_from_crd(&obj)
-        .map_err(Error::Converter)?;
-    ctx.command_tx
+    let obj_ref = ObjectRef::from_obj(&*obj);
+
+    let applied = ctx.applied.lock().unwrap().remove(&obj_ref);
+    let applied = match applied {
+        Some(applied) => Some(applied),
+        None => applied_from_status(&obj)?,
+    };
+    let (gateway_ref, id) = match applied {
+        Some(applied) => (applied.gateway_ref, applied.mapping_id),
+        None => match ctx.converter.mapping_id_from_crd(&obj) {
+            Ok(id) => (obj.spec.gateway_ref.clone(), id),
+            // The mapping has never been requested without the nonce.
+            Err(pcp::ConversionError::MissingNonce) => return Ok(Action::await_change()),
+            Err(error) => return Err(Error::Converter(error)),
+        },
+    };
+
+    // The mappings are gone along with the gateway.
+    let Some(gateway) = ctx.gateways.get(gateway_ref.as_deref()) else {
+        return Ok(Action::await_change());
+    };
+
+    gateway
+        .allocations
+        .lock()
+        .unwrap()
+        .release(&allocation::Owner::Map(obj_ref));
+
+    gateway
+        .command_tx
         .send_timeout(
             pcp_client::Command::RemoveDesired(id),
             ctx.params.pcp_client_command_timeout,
@@ -90,7 +399,8 @@ pub async fn cleanup(obj: Arc<crd::PCPMap>, ctx: Arc<Context>) -> Result<Action,
         .await?;
 
     let (tx, rx) = tokio::sync::oneshot::channel();
-    ctx.command_tx
+    gateway
+        .command_tx
         .send_timeout(
             pcp_client::Command::HasState(id, tx),
             ctx.params.pcp_client_command_timeout,
@@ -100,10 +410,152 @@ pub async fn cleanup(obj: Arc<crd::PCPMap>, ctx: Arc<Context>) -> Result<Action,
     let has_state = rx.await.map_err(Error::ReplyRxClosed)?;
 
     if has_state {
-        Err(Error::CleanUpInProgress)
-    } else {
-        Ok(Action::await_change())
+        return Err(Error::CleanUpInProgress);
     }
+
+    let note = format!(
+        "the mapping has been removed from the PCP server, last result code {}",
+        event::result_code(obj.status.as_ref()),
+    );
+    event::publish(
+        &ctx.k8s_client,
+        &ctx.reporter,
+        obj.object_ref(&()),
+        events::Event {
+            type_: events::EventType::Normal,
+            reason: event::reason::CLEANUP_COMPLETED.to_owned(),
+            note: Some(note),
+            action: event::action::CLEANUP.to_owned(),
+            secondary: None,
+        },
+    )
+    .await;
+
+    Ok(Action::await_change())
+}
+
+/// Record the external port conflict at the resource status and as an event.
+///
+/// The services have no conditions to report at, so only get the event.
+pub(crate) async fn report_conflict(
+    client: &kube::Client,
+    reporter: &events::Reporter,
+    owner: &allocation::Owner,
+    holder: &allocation::Claimant,
+) -> Result<(), Error> {
+    let message = format!(
+        "the external port is claimed by {}, which was created earlier",
+        holder.owner
+    );
+
+    let obj = match owner {
+        allocation::Owner::Map(obj) => obj.clone(),
+        allocation::Owner::Service(svc, _) => {
+            event::publish(
+                client,
+                reporter,
+                svc.clone().into(),
+                events::Event {
+                    type_: events::EventType::Warning,
+                    reason: event::reason::EXTERNAL_PORT_CONFLICT.to_owned(),
+                    note: Some(message),
+                    action: event::action::CLAIM_EXTERNAL_PORT.to_owned(),
+                    secondary: None,
+                },
+            )
+            .await;
+            return Ok(());
+        }
+    };
+
+    let api = api_for(&obj, client);
+    let mut attempt = 1;
+    let status = loop {
+        let current = api.get_status(&obj.name).await.map_err(Error::Kube)?;
+        let generation = current.metadata.generation;
+        let now =
+            k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(k8s_openapi::chrono::Utc::now());
+
+        let status = current.status.unwrap_or_default();
+        let mut conditions = status.conditions.clone();
+        for (type_, reason) in [
+            (crd::condition::CONFLICT, "ExternalPortClaimed"),
+            (crd::condition::READY, "Conflict"),
+        ] {
+            condition::set(
+                &mut conditions,
+                condition::new(
+                    type_,
+                    type_ == crd::condition::CONFLICT,
+                    reason,
+                    message.clone(),
+                    generation,
+                    &now,
+                ),
+            );
+        }
+
+        // The resource is requeued while the conflict lasts, so only the changes are reported.
+        let conflict_changed = {
+            let conflict = |conditions: &[crd::Condition]| {
+                conditions
+                    .iter()
+                    .find(|condition| condition.type_ == crd::condition::CONFLICT)
+                    .map(|condition| {
+                        (
+                            condition.status.clone(),
+                            condition.reason.clone(),
+                            condition.message.clone(),
+                        )
+                    })
+            };
+            conflict(&status.conditions) != conflict(&conditions)
+        };
+        if !conflict_changed {
+            return Ok(());
+        }
+
+        let patch = kube::api::Patch::Merge(serde_json::json!({
+            "metadata": {
+                "resourceVersion": current.metadata.resource_version,
+            },
+            "status": {
+                "conditions": conditions,
+            },
+        }));
+        match api
+            .patch_status(&obj.name, &kube::api::PatchParams::default(), &patch)
+            .await
+        {
+            Ok(_) => break status,
+            Err(error)
+                if condition::is_write_conflict(&error) && attempt < condition::WRITE_ATTEMPTS =>
+            {
+                attempt += 1;
+            }
+            Err(error) => return Err(Error::Kube(error)),
+        }
+    };
+
+    let note = format!(
+        "{message}, last result code {}",
+        event::result_code(Some(&status))
+    );
+    event::publish(
+        client,
+        reporter,
+        obj.into(),
+        events::Event {
+            type_: events::EventType::Warning,
+            reason: event::reason::EXTERNAL_PORT_CONFLICT.to_owned(),
+            note: Some(note),
+            action: event::action::CLAIM_EXTERNAL_PORT.to_owned(),
+            secondary: None,
+        },
+    )
+    .await;
+
+    Ok(())
 }
 
 /// Reconcile the changes at the API.
@@ -111,12 +563,7 @@ pub async fn reconcile(
     obj: Arc<crd::PCPMap>,
     ctx: Arc<Context>,
 ) -> Result<Action, finalizer::Error<Error>> {
-    let client = ctx.k8s_client.clone();
-    let maybe_namespace = obj.metadata.namespace.as_deref();
-    let api = match maybe_namespace {
-        Some(namespace) => kube::Api::namespaced(client, namespace),
-        None => kube::Api::all(client),
-    };
+    let api = api_for(&ObjectRef::from_obj(&*obj), &ctx.k8s_client);
     finalizer(&api, &ctx.params.finalizer_name, obj, {
         let ctx = Arc::clone(&ctx);
         |event| async move {
@@ -154,10 +601,18 @@ pub enum Error {
     #[error("unable to covert the CRD into PCP type: {0}")]
     Converter(pcp::ConversionError),
 
+    /// A Kubernetes API call has failed.
+    #[error("Kubernetes API error: {0}")]
+    Kube(kube::Error),
+
     /// Waiting for the PCP client state reporting failed.
     #[error("PCP client response not delivered: {0}")]
     ReplyRxClosed(tokio::sync::oneshot::error::RecvError),
 
+    /// The referenced gateway is not known.
+    #[error("unknown gateway: {0}")]
+    UnknownGateway(Arc<str>),
+
     /// An error to stop the finalizer from being removed.
     #[error("the cleanup is still in progress")]
     CleanUpInProgress,
@@ -0,0 +1,521 @@
+//! `LoadBalancer` [`Service`] controller.
+//!
+//! Forwards the ports of the `LoadBalancer` services of our class from the router to the node
+//! ports of the controller node.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt as _;
+use k8s_openapi::api::core::v1::Service;
+use kube::{
+    runtime::{controller::Action, events, finalizer, reflector::ObjectRef},
+    ResourceExt as _,
+};
+
+use crate::{
+    allocation, gateway, pcp,
+    reconciler::{self, Error},
+};
+
+/// The default `loadBalancerClass` of the services to manage.
+pub const DEFAULT_LOAD_BALANCER_CLASS: &str = "port-forward.io/pcp";
+
+/// The context of the service reconciler.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Context {
+    /// The execution params of the reconciler.
+    pub params: reconciler::Params,
+
+    /// The `loadBalancerClass` of the services to manage.
+    pub load_balancer_class: Arc<str>,
+
+    /// The gateways to request the mappings at.
+    ///
+    /// The services always use the default one.
+    pub gateways: Arc<gateway::Gateways>,
+
+    /// The Kubernetes API client for controlled resources.
+    #[derivative(Debug = "ignore")]
+    pub k8s_client: kube::Client,
+
+    /// The reporter of the events.
+    pub reporter: events::Reporter,
+
+    /// The converter for the PCP types.
+    pub converter: pcp::Converter,
+
+    /// The mappings that were applied for each service.
+    ///
+    /// Used to remove the mappings of the ports that are no longer at the service.
+    pub applied: Mutex<HashMap<ObjectRef<Service>, HashSet<pcp_client::mapping::Id>>>,
+
+    /// The controller metrics.
+    #[derivative(Debug = "ignore")]
+    pub metrics: Arc<crate::metrics::Metrics>,
+
+    /// The leadership of this replica, the reconciles only run at the leader.
+    #[derivative(Debug = "ignore")]
+    pub leadership: leader_election::Leadership,
+}
+
+impl Context {
+    /// Check if the service is to be managed by us.
+    fn is_managed(&self, svc: &Service) -> bool {
+        let Some(spec) = &svc.spec else {
+            return false;
+        };
+        spec.type_.as_deref() == Some("LoadBalancer")
+            && spec.load_balancer_class.as_deref() == Some(&*self.load_balancer_class)
+    }
+
+    /// Send a command to the PCP client of the gateway.
+    async fn send(
+        &self,
+        gateway: &gateway::Gateway,
+        command: pcp_client::Command,
+    ) -> Result<(), Error> {
+        gateway
+            .command_tx
+            .send_timeout(command, self.params.pcp_client_command_timeout)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Create the PCP client mappings for every port of the service.
+pub fn mappings_from_service(
+    converter: &pcp::Converter,
+    svc: &Service,
+) -> Result<Vec<pcp_client::Mapping>, pcp::ConversionError> {
+    let internal_ip = converter
+        .client_ip_address
+        .ok_or(pcp::ConversionError::NoClientAddress)?;
+    let nonce = pcp::nonce_from_meta(&svc.metadata)?;
+
+    let Some(spec) = &svc.spec else {
+        return Ok(Vec::new());
+    };
+
+    let filters = match &spec.load_balancer_source_ranges {
+        Some(ranges) if !ranges.is_empty() => Some(pcp_client::mapping::option::PcpOption {
+            is_optional: false,
+            payload: ranges
+                .iter()
+                .map(|range| pcp::parse_cidr(range))
+                .collect::<Result<_, _>>()?,
+        }),
+        _ => None,
+    };
+
+    spec.ports
+        .iter()
+        .flatten()
+        // The ports are not exposed at the node until it is allocated.
+        .filter_map(|port| Some((port, port.node_port?)))
+        .map(|(port, node_port)| {
+            let protocol = pcp::protocol_from_name(port.protocol.as_deref().unwrap_or("TCP"))?;
+
+            let id = pcp_client::mapping::Id {
+                protocol,
+                internal_ip,
+                internal_port: node_port
+                    .try_into()
+                    .map_err(pcp::ConversionError::InvalidPortNumber)?,
+                nonce,
+            };
+
+            let params = pcp_client::mapping::Params {
+                lifetime: converter.lifetime,
+                external_port: port
+                    .port
+                    .try_into()
+                    .map_err(pcp::ConversionError::InvalidPortNumber)?,
+                exteranl_ip: pcp_primitives::Address::UNSPECIFIED,
+                third_party: None,
+                prefer_failure: Some(pcp_client::mapping::option::PcpOption {
+                    is_optional: false,
+                    payload: (),
+                }),
+                filters: filters.clone(),
+            };
+
+            Ok(pcp_client::Mapping { id, params })
+        })
+        .collect()
+}
+
+/// Apply the service update that happened at the API to the PCP client state.
+pub async fn apply(svc: Arc<Service>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let svc = reconciler::ensure_nonce(svc, &ctx.k8s_client).await?;
+    let svc_ref = ObjectRef::from_obj(&*svc);
+    let gateway = ctx.gateways.default_gateway();
+
+    let mappings = mappings_from_service(&ctx.converter, &svc).map_err(Error::Converter)?;
+    let ids: HashSet<_> = mappings.iter().map(|mapping| mapping.id).collect();
+
+    let stale: Vec<_> = {
+        let mut applied = ctx.applied.lock().unwrap();
+        let prev = applied
+            .insert(svc_ref.clone(), ids.clone())
+            .unwrap_or_default();
+        prev.difference(&ids).copied().collect()
+    };
+
+    for id in stale {
+        gateway
+            .allocations
+            .lock()
+            .unwrap()
+            .release(&allocation::Owner::Service(svc_ref.clone(), id));
+        ctx.send(&gateway, pcp_client::Command::RemoveDesired(id))
+            .await?;
+    }
+
+    let mut conflict = false;
+    for mapping in mappings {
+        let claimant = allocation::Claimant::service(&svc, mapping.id);
+        let entry = allocation_registry::Entry::new(
+            mapping.id.protocol,
+            mapping.params.external_port,
+            mapping.id.internal_ip,
+            mapping.id.internal_port,
+        );
+        let claim = gateway
+            .allocations
+            .lock()
+            .unwrap()
+            .claim(entry, claimant.clone());
+
+        match claim {
+            allocation::Claim::Granted { evicted: None } => {}
+            allocation::Claim::Granted {
+                evicted: Some(evicted),
+            } => {
+                ctx.send(
+                    &gateway,
+                    pcp_client::Command::RemoveDesired(evicted.mapping_id),
+                )
+                .await?;
+                reconciler::report_conflict(
+                    &ctx.k8s_client,
+                    &ctx.reporter,
+                    &evicted.owner,
+                    &claimant,
+                )
+                .await?;
+            }
+            allocation::Claim::Rejected { holder } => {
+                ctx.send(&gateway, pcp_client::Command::RemoveDesired(mapping.id))
+                    .await?;
+                reconciler::report_conflict(
+                    &ctx.k8s_client,
+                    &ctx.reporter,
+                    &claimant.owner,
+                    &holder,
+                )
+                .await?;
+                conflict = true;
+                continue;
+            }
+        }
+
+        ctx.send(&gateway, pcp_client::Command::UpsertDesired(mapping))
+            .await?;
+    }
+
+    let mut external_ips = BTreeSet::new();
+    let mut all_effective = true;
+    for id in ids {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        ctx.send(&gateway, pcp_client::Command::GetEffective(id, tx))
+            .await?;
+
+        match rx.await.map_err(Error::ReplyRxClosed)? {
+            Some(incoming) => {
+                external_ips.insert(pcp_ip_conv::split(
+                    incoming.packet_opcode.assigned_external_ip_address,
+                ));
+            }
+            None => all_effective = false,
+        }
+    }
+
+    if !external_ips.is_empty() {
+        update_ingress(&svc, &ctx, external_ips).await?;
+    }
+
+    Ok(if conflict {
+        // Retry in case the holder goes away.
+        Action::requeue(ctx.params.error_requeue_duration)
+    } else if all_effective {
+        Action::requeue(std::time::Duration::from_secs(60))
+    } else {
+        Action::requeue(ctx.params.pending_requeue_duration)
+    })
+}
+
+/// Write the external IPs into the service load balancer status.
+async fn update_ingress(
+    svc: &Service,
+    ctx: &Context,
+    external_ips: BTreeSet<std::net::IpAddr>,
+) -> Result<(), Error> {
+    let ingress: Vec<_> = external_ips
+        .into_iter()
+        .map(|ip| k8s_openapi::api::core::v1::LoadBalancerIngress {
+            ip: Some(ip.to_string()),
+            ..Default::default()
+        })
+        .collect();
+
+    let current = svc
+        .status
+        .as_ref()
+        .and_then(|status| status.load_balancer.as_ref())
+        .and_then(|load_balancer| load_balancer.ingress.as_ref());
+    if current == Some(&ingress) {
+        return Ok(());
+    }
+
+    let patch = kube::api::Patch::Merge(serde_json::json!({
+        "status": {
+            "loadBalancer": {
+                "ingress": ingress,
+            },
+        },
+    }));
+
+    reconciler::api_for(&ObjectRef::from_obj(svc), &ctx.k8s_client)
+        .patch_status(&svc.name_any(), &kube::api::PatchParams::default(), &patch)
+        .await
+        .map_err(Error::Kube)?;
+
+    Ok(())
+}
+
+/// Run the cleanup process for the mappings of the service at the PCP client in response
+/// to the service deletion at the API, or the service no longer being managed by us.
+pub async fn cleanup(svc: Arc<Service>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let svc_ref = ObjectRef::from_obj(&*svc);
+    let gateway = ctx.gateways.default_gateway();
+
+    let mut ids = ctx
+        .applied
+        .lock()
+        .unwrap()
+        .remove(&svc_ref)
+        .unwrap_or_default();
+
+    // The controller might have been restarted since the mappings were applied.
+    if let Ok(mappings) = mappings_from_service(&ctx.converter, &svc) {
+        ids.extend(mappings.iter().map(|mapping| mapping.id));
+    }
+
+    for &id in &ids {
+        gateway
+            .allocations
+            .lock()
+            .unwrap()
+            .release(&allocation::Owner::Service(svc_ref.clone(), id));
+        ctx.send(&gateway, pcp_client::Command::RemoveDesired(id))
+            .await?;
+    }
+
+    for id in ids {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        ctx.send(&gateway, pcp_client::Command::HasState(id, tx))
+            .await?;
+
+        if rx.await.map_err(Error::ReplyRxClosed)? {
+            return Err(Error::CleanUpInProgress);
+        }
+    }
+
+    Ok(Action::await_change())
+}
+
+/// Reconcile the changes at the API.
+pub async fn reconcile(
+    svc: Arc<Service>,
+    ctx: Arc<Context>,
+) -> Result<Action, finalizer::Error<Error>> {
+    let has_finalizer = svc
+        .finalizers()
+        .iter()
+        .any(|finalizer| **finalizer == *ctx.params.finalizer_name);
+    if !has_finalizer && !ctx.is_managed(&svc) {
+        return Ok(Action::await_change());
+    }
+
+    // The finalizer is only removed on the deletion otherwise.
+    if !ctx.is_managed(&svc) && svc.metadata.deletion_timestamp.is_none() {
+        return release(svc, ctx).await;
+    }
+
+    let api = reconciler::api_for(&ObjectRef::from_obj(&*svc), &ctx.k8s_client);
+    finalizer(&api, &ctx.params.finalizer_name, svc, {
+        let ctx = Arc::clone(&ctx);
+        |event| async move {
+            match event {
+                finalizer::Event::Apply(svc) => apply(svc, ctx).await,
+                finalizer::Event::Cleanup(svc) => cleanup(svc, ctx).await,
+            }
+        }
+    })
+    .await
+}
+
+/// Let go of the service that is no longer managed by us, but still exists.
+///
+/// Once the mappings are cleaned up, the load balancer status is cleared and the finalizer
+/// is removed.
+async fn release(svc: Arc<Service>, ctx: Arc<Context>) -> Result<Action, finalizer::Error<Error>> {
+    let action = cleanup(Arc::clone(&svc), Arc::clone(&ctx))
+        .await
+        .map_err(finalizer::Error::CleanupFailed)?;
+
+    let api = reconciler::api_for(&ObjectRef::from_obj(&*svc), &ctx.k8s_client);
+    let name = svc.name_any();
+
+    let has_ingress = svc
+        .status
+        .as_ref()
+        .and_then(|status| status.load_balancer.as_ref())
+        .and_then(|load_balancer| load_balancer.ingress.as_ref())
+        .is_some();
+    if has_ingress {
+        let patch = kube::api::Patch::Merge(serde_json::json!({
+            "status": {
+                "loadBalancer": {
+                    "ingress": null,
+                },
+            },
+        }));
+        api.patch_status(&name, &kube::api::PatchParams::default(), &patch)
+            .await
+            .map_err(|error| finalizer::Error::CleanupFailed(Error::Kube(error)))?;
+    }
+
+    let finalizers: Vec<_> = svc
+        .finalizers()
+        .iter()
+        .filter(|finalizer| **finalizer != *ctx.params.finalizer_name)
+        .collect();
+    // The resource version makes the patch fail rather than drop the finalizers added
+    // in the meantime.
+    let patch = kube::api::Patch::Merge(serde_json::json!({
+        "metadata": {
+            "finalizers": finalizers,
+            "resourceVersion": svc.resource_version(),
+        },
+    }));
+    api.patch(&name, &kube::api::PatchParams::default(), &patch)
+        .await
+        .map_err(finalizer::Error::RemoveFinalizer)?;
+
+    Ok(action)
+}
+
+/// Apply the error.
+pub fn error_policy(
+    _svc: Arc<Service>,
+    error: &finalizer::Error<Error>,
+    ctx: Arc<Context>,
+) -> Action {
+    match error {
+        finalizer::Error::CleanupFailed(Error::CleanUpInProgress)
+        | finalizer::Error::ApplyFailed(Error::CleanUpInProgress) => {
+            Action::requeue(ctx.params.cleanup_requeue_duration)
+        }
+        _ => Action::requeue(ctx.params.error_requeue_duration),
+    }
+}
+
+/// Run the controller until it exits.
+pub async fn run(controller: kube::runtime::Controller<Service>, ctx: Arc<Context>) {
+    let reconcile = crate::metrics::instrument(Arc::clone(&ctx.metrics), "service", reconcile);
+    let reconcile = crate::leader::gate(ctx.leadership.clone(), reconcile);
+    controller
+        .reconcile_all_on(ctx.leadership.transitions())
+        .run(reconcile, error_policy, ctx)
+        .for_each(|result| async move {
+            match result {
+                Ok((obj, action)) => tracing::info!(message = "reconciled", ?obj, ?action),
+                Err(error) => tracing::error!(message = "reconcile failed", ?error),
+            }
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{ServicePort, ServiceSpec};
+
+    use super::*;
+
+    #[test]
+    fn mappings() {
+        let converter = pcp::Converter {
+            lifetime: 3600,
+            client_ip_address: Some(std::net::Ipv4Addr::new(192, 0, 2, 10).to_ipv6_mapped()),
+        };
+
+        let mut svc = Service {
+            metadata: kube::core::ObjectMeta {
+                annotations: Some(
+                    [(
+                        crd::annotation::NONCE.to_owned(),
+                        "0102030405060708090a0b0c".to_owned(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(ServiceSpec {
+                type_: Some("LoadBalancer".to_owned()),
+                ports: Some(vec![
+                    ServicePort {
+                        port: 25565,
+                        node_port: Some(30565),
+                        ..Default::default()
+                    },
+                    ServicePort {
+                        port: 53,
+                        node_port: Some(30053),
+                        protocol: Some("UDP".to_owned()),
+                        ..Default::default()
+                    },
+                    ServicePort {
+                        port: 80,
+                        ..Default::default()
+                    },
+                ]),
+                load_balancer_source_ranges: Some(vec!["203.0.113.0/24".to_owned()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mappings = mappings_from_service(&converter, &svc).unwrap();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].id.protocol, pcp_consts::protocol::TCP);
+        assert_eq!(mappings[0].id.internal_port, 30565);
+        assert_eq!(mappings[0].params.external_port, 25565);
+        assert_eq!(mappings[1].id.protocol, pcp_consts::protocol::UDP);
+        assert_eq!(mappings[1].id.internal_port, 30053);
+        assert_eq!(
+            mappings[1].params.filters.as_ref().unwrap().payload.len(),
+            1
+        );
+
+        svc.metadata.annotations = None;
+        assert!(matches!(
+            mappings_from_service(&converter, &svc),
+            Err(pcp::ConversionError::MissingNonce)
+        ));
+    }
+}
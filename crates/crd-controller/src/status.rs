@@ -1,8 +1,17 @@
 //! The status listener.
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use futures::{Stream, StreamExt as _};
+use kube::{runtime::events, Resource as _};
+
+use crate::{condition, event};
 
 /// Indexer specialized for status listener.
 pub mod indexer {
@@ -54,11 +63,100 @@ pub struct Listener {
     /// The Kubernetes client for executing the operations.
     #[derivative(Debug = "ignore")]
     pub kube_client: kube::Client,
+
+    /// The controller metrics.
+    #[derivative(Debug = "ignore")]
+    pub metrics: Arc<crate::metrics::Metrics>,
+
+    /// The reporter of the events.
+    pub reporter: events::Reporter,
+
+    /// Set once the indexer is ready and the notifications are no longer stashed.
+    pub indexer_ready: Arc<AtomicBool>,
 }
 
 impl Listener {
-    /// Handle a mapping status notification.
+    /// Handle a PCP client notification.
     async fn handle_notification(
+        &self,
+        index_reader: &indexer::Reader<'_>,
+        notification: pcp_client::Notification,
+    ) -> Result<(), kube::error::Error> {
+        match notification {
+            pcp_client::Notification::Incoming(incoming) => {
+                self.handle_incoming(index_reader, incoming).await
+            }
+            pcp_client::Notification::ServerStateLost(details) => {
+                self.handle_server_state_lost(index_reader, details).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Record the server state loss at all of the affected resources.
+    async fn handle_server_state_lost(
+        &self,
+        index_reader: &indexer::Reader<'_>,
+        details: pcp_client::ServerStateLost,
+    ) {
+        let now =
+            k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(k8s_openapi::chrono::Utc::now());
+
+        let patch = kube::api::Patch::Merge(serde_json::json!({
+            "status": {
+                "lastServerStateLoss": now,
+            },
+        }));
+
+        for id in &details.mapping_ids {
+            let Some(kube_ref) = index_reader.get(id) else {
+                continue;
+            };
+
+            let api =
+                kube::Api::<crd::PCPMap>::namespaced(self.kube_client.clone(), &kube_ref.namespace);
+
+            let obj = match api
+                .patch_status(&kube_ref.name, &kube::api::PatchParams::default(), &patch)
+                .await
+            {
+                Ok(obj) => obj,
+                Err(error) => {
+                    self.metrics.status_patch_failures.inc();
+                    tracing::error!(
+                        message = "error while recording the server state loss",
+                        ?error,
+                        ?kube_ref,
+                        server_address = %details.server_address,
+                    );
+                    continue;
+                }
+            };
+
+            let note = format!(
+                "the PCP server {} has reset its epoch to {}s, requesting the mapping again, last result code {}",
+                details.server_address,
+                details.epoch_time,
+                event::result_code(obj.status.as_ref()),
+            );
+            event::publish(
+                &self.kube_client,
+                &self.reporter,
+                obj.object_ref(&()),
+                events::Event {
+                    type_: events::EventType::Warning,
+                    reason: event::reason::SERVER_EPOCH_RESET.to_owned(),
+                    note: Some(note),
+                    action: event::action::MAP.to_owned(),
+                    secondary: None,
+                },
+            )
+            .await;
+        }
+    }
+
+    /// Handle a mapping status notification.
+    async fn handle_incoming(
         &self,
         index_reader: &indexer::Reader<'_>,
         incoming: pcp_client::mapping::Incoming,
@@ -69,27 +167,65 @@ impl Listener {
             return Ok(());
         };
 
-        let external_endpoint = std::net::SocketAddr::new(
-            pcp_ip_conv::split(incoming.packet_opcode.assigned_external_ip_address),
-            incoming.packet_opcode.assigned_external_port,
-        );
-
         let api =
             kube::Api::<crd::PCPMap>::namespaced(self.kube_client.clone(), &kube_ref.namespace);
 
-        let pp = kube::api::PatchParams {
-            dry_run: false,
-            force: false,
-            field_manager: Some("port-forward-controller".into()),
-            field_validation: Some(kube::api::ValidationDirective::Strict),
-        };
+        let mut attempt = 1;
+        let (obj, event) = loop {
+            let obj = api.get_status(&kube_ref.name).await?;
 
-        let patch = kube::api::Patch::Apply(crd::PCPMapStatus {
-            external_endpoint: Some(external_endpoint),
-            ..Default::default()
-        });
+            let now = k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(
+                k8s_openapi::chrono::Utc::now(),
+            );
 
-        api.patch_status(&kube_ref.name, &pp, &patch).await?;
+            let prev = obj.status.clone().unwrap_or_default();
+            let mut status = prev.clone();
+            update_status(&mut status, &obj, &incoming, &now);
+            let event = response_event(
+                &prev,
+                &status,
+                exact_port(&obj.spec),
+                incoming.packet_header.result_code,
+            );
+            // Owned by the reconciler, so not to overwrite its concurrent update.
+            status.applied_mapping = None;
+
+            // The conditions are written as a whole, so the concurrent updates to them
+            // must not be lost.
+            let patch = kube::api::Patch::Merge(serde_json::json!({
+                "metadata": {
+                    "resourceVersion": obj.metadata.resource_version,
+                },
+                "status": status,
+            }));
+
+            match api
+                .patch_status(&kube_ref.name, &kube::api::PatchParams::default(), &patch)
+                .await
+            {
+                Ok(_) => break (obj, event),
+                Err(error)
+                    if condition::is_write_conflict(&error)
+                        && attempt < condition::WRITE_ATTEMPTS =>
+                {
+                    attempt += 1;
+                }
+                Err(error) => {
+                    self.metrics.status_patch_failures.inc();
+                    return Err(error);
+                }
+            }
+        };
+
+        if let Some(event) = event {
+            event::publish(
+                &self.kube_client,
+                &self.reporter,
+                obj.object_ref(&()),
+                event,
+            )
+            .await;
+        }
 
         Ok(())
     }
@@ -98,7 +234,7 @@ impl Listener {
     pub async fn lifecycle_loop<Watcher>(
         mut self,
         watcher: Watcher,
-        mut notifications_rx: tokio::sync::mpsc::Receiver<pcp_client::mapping::Incoming>,
+        mut notifications_rx: tokio::sync::mpsc::Receiver<pcp_client::Notification>,
     ) where
         Watcher: Stream<
                 Item = Result<
@@ -108,6 +244,7 @@ impl Listener {
             > + Send,
     {
         let mut stashed_notifications = HashMap::new();
+        let mut stashed_state_losses = Vec::new();
         let mut watcher = std::pin::pin!(watcher);
         loop {
             tokio::select! {
@@ -127,7 +264,17 @@ impl Listener {
                     self.indexer.handle_event(event);
 
                     if let Ok(reader) = self.indexer.reader() {
-                        for (_, notification) in stashed_notifications.drain() {
+                        self.indexer_ready.store(true, Ordering::Relaxed);
+
+                        let stashed = stashed_notifications
+                            .drain()
+                            .map(|(_, incoming)| pcp_client::Notification::Incoming(incoming))
+                            .chain(
+                                stashed_state_losses
+                                    .drain(..)
+                                    .map(pcp_client::Notification::ServerStateLost),
+                            );
+                        for notification in stashed {
                             if let Err(error) = self.handle_notification(&reader, notification).await {
                                 tracing::error!(message = "error while handling stashed notification", ?error);
                             }
@@ -140,7 +287,14 @@ impl Listener {
                     };
 
                     let Ok(reader) = self.indexer.reader() else {
-                        stashed_notifications.insert(notification.id(), notification);
+                        match notification {
+                            pcp_client::Notification::Incoming(incoming) => {
+                                stashed_notifications.insert(incoming.id(), incoming);
+                            }
+                            pcp_client::Notification::ServerStateLost(details) => {
+                                stashed_state_losses.push(details);
+                            }
+                        }
                         continue;
                     };
 
@@ -152,3 +306,300 @@ impl Listener {
         }
     }
 }
+
+/// The event about the PCP server response, if it changes anything worth reporting.
+fn response_event(
+    prev: &crd::PCPMapStatus,
+    status: &crd::PCPMapStatus,
+    requested_port: Option<crd::PortNumber>,
+    result_code: pcp_primitives::ResultCode,
+) -> Option<events::Event> {
+    let result_code_name = event::result_code(Some(status));
+
+    let (reason, note) = match pcp_lifecycle::result::classify(result_code) {
+        pcp_lifecycle::result::Class::Success => {
+            // Released mappings have no endpoint.
+            let external_endpoint = status.external_endpoint?;
+            let assigned_port = external_endpoint.port();
+            let requested_port = requested_port?;
+            if assigned_port == requested_port || prev.external_endpoint == Some(external_endpoint)
+            {
+                return None;
+            }
+            let note = format!(
+                "requested external port {requested_port}, but got {assigned_port}, result code {result_code_name}"
+            );
+            (event::reason::EXTERNAL_PORT_MISMATCH, note)
+        }
+        pcp_lifecycle::result::Class::Error(_) => {
+            // The retries keep failing the same way.
+            if prev.last_result_code == status.last_result_code {
+                return None;
+            }
+            let reason = if result_code == pcp_consts::result_code::CANNOT_PROVIDE_EXTERNAL {
+                event::reason::EXTERNAL_PORT_CONFLICT
+            } else if prev.external_endpoint.is_some() {
+                event::reason::RENEWAL_FAILED
+            } else {
+                event::reason::MAPPING_FAILED
+            };
+            let note = format!("the PCP server responded with {result_code_name}");
+            (reason, note)
+        }
+    };
+
+    Some(events::Event {
+        type_: events::EventType::Warning,
+        reason: reason.to_owned(),
+        note: Some(note),
+        action: event::action::MAP.to_owned(),
+        secondary: None,
+    })
+}
+
+/// The external port the mapping must get, if any.
+fn exact_port(spec: &crd::PCPMapSpec) -> Option<crd::PortNumber> {
+    spec.from.filter(|_| !spec.suggest_only)
+}
+
+/// Update the status according to the PCP server response.
+fn update_status(
+    status: &mut crd::PCPMapStatus,
+    obj: &crd::PCPMap,
+    incoming: &pcp_client::mapping::Incoming,
+    now: &crd::Time,
+) {
+    let generation = obj.metadata.generation;
+    let result_code = incoming.packet_header.result_code;
+    let result_code_name = pcp_consts::result_code::name(result_code)
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| format!("UNKNOWN({result_code})"));
+    let lifetime = incoming.packet_header.lifetime;
+
+    status.last_result_code = Some(result_code_name.clone());
+
+    // The spec is only effective once the mapping applied for it is granted.
+    let granted = lifetime != 0
+        && matches!(
+            pcp_lifecycle::result::classify(result_code),
+            pcp_lifecycle::result::Class::Success
+        );
+    if let Some(applied) = &status.applied_mapping {
+        let id = incoming.id();
+        let for_applied = crate::pcp::applied_mapping_to_crd(
+            &id,
+            applied.gateway_ref.clone(),
+            applied.generation,
+        ) == *applied;
+        if granted && for_applied {
+            status.observed_generation = applied.generation;
+        }
+    }
+
+    let conditions = &mut status.conditions;
+
+    match pcp_lifecycle::result::classify(result_code) {
+        pcp_lifecycle::result::Class::Success if lifetime == 0 => {
+            status.external_endpoint = None;
+            status.assigned_port = None;
+            status.granted_lifetime = None;
+
+            condition::set(
+                conditions,
+                condition::new(
+                    crd::condition::READY,
+                    false,
+                    "Released",
+                    "the mapping has been released at the PCP server".to_owned(),
+                    generation,
+                    now,
+                ),
+            );
+        }
+        pcp_lifecycle::result::Class::Success => {
+            let external_endpoint = std::net::SocketAddr::new(
+                pcp_ip_conv::split(incoming.packet_opcode.assigned_external_ip_address),
+                incoming.packet_opcode.assigned_external_port,
+            );
+
+            status.protocol_number = Some(incoming.packet_opcode.protocol);
+            status.internal_ip = Some(pcp_ip_conv::split(incoming.received_on).to_string());
+            status.external_endpoint = Some(external_endpoint);
+            status.assigned_port = Some(external_endpoint.port());
+            status.granted_lifetime = Some(lifetime);
+
+            condition::set(
+                conditions,
+                condition::new(
+                    crd::condition::READY,
+                    true,
+                    "Mapped",
+                    format!("mapped at {external_endpoint} for {lifetime}s"),
+                    generation,
+                    now,
+                ),
+            );
+            condition::set(
+                conditions,
+                condition::new(
+                    crd::condition::DEGRADED,
+                    false,
+                    "Succeeded",
+                    String::new(),
+                    generation,
+                    now,
+                ),
+            );
+
+            let assigned_port = external_endpoint.port();
+            let mismatch =
+                exact_port(&obj.spec).filter(|requested_port| *requested_port != assigned_port);
+            let conflict = if let Some(requested_port) = mismatch {
+                condition::new(
+                    crd::condition::CONFLICT,
+                    true,
+                    "ExternalPortMismatch",
+                    format!("requested external port {requested_port}, but got {assigned_port}"),
+                    generation,
+                    now,
+                )
+            } else {
+                condition::new(
+                    crd::condition::CONFLICT,
+                    false,
+                    "NoConflict",
+                    String::new(),
+                    generation,
+                    now,
+                )
+            };
+            condition::set(conditions, conflict);
+        }
+        pcp_lifecycle::result::Class::Error(kind) => {
+            let (reason, retry) = match kind {
+                pcp_lifecycle::result::ErrorKind::ShortLifetime => {
+                    ("ShortLifetimeError", "will retry")
+                }
+                pcp_lifecycle::result::ErrorKind::LongLifetime => {
+                    ("LongLifetimeError", "will retry, but unlikely to succeed")
+                }
+                pcp_lifecycle::result::ErrorKind::Unknown => {
+                    ("UnknownError", "the result code is not known, will retry")
+                }
+            };
+            let message =
+                format!("the PCP server responded with {result_code_name}, {retry} in {lifetime}s");
+
+            condition::set(
+                conditions,
+                condition::new(
+                    crd::condition::READY,
+                    false,
+                    reason,
+                    message.clone(),
+                    generation,
+                    now,
+                ),
+            );
+            condition::set(
+                conditions,
+                condition::new(
+                    crd::condition::DEGRADED,
+                    true,
+                    reason,
+                    message.clone(),
+                    generation,
+                    now,
+                ),
+            );
+
+            if result_code == pcp_consts::result_code::CANNOT_PROVIDE_EXTERNAL {
+                condition::set(
+                    conditions,
+                    condition::new(
+                        crd::condition::CONFLICT,
+                        true,
+                        "ExternalUnavailable",
+                        message,
+                        generation,
+                        now,
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_events() {
+        let granted = |port, result_code: &str| crd::PCPMapStatus {
+            external_endpoint: Some(std::net::SocketAddr::from(([203, 0, 113, 1], port))),
+            last_result_code: Some(result_code.to_owned()),
+            ..Default::default()
+        };
+        fn reason(
+            prev: &crd::PCPMapStatus,
+            status: &crd::PCPMapStatus,
+            result_code: pcp_primitives::ResultCode,
+        ) -> Option<String> {
+            response_event(prev, status, Some(80), result_code).map(|event| event.reason)
+        }
+
+        let none = crd::PCPMapStatus::default();
+        let success = pcp_consts::result_code::SUCCESS;
+        let no_resources = pcp_consts::result_code::NO_RESOURCES;
+        let cannot_provide_external = pcp_consts::result_code::CANNOT_PROVIDE_EXTERNAL;
+
+        assert_eq!(reason(&none, &granted(80, "SUCCESS"), success), None);
+        // Any port would do.
+        assert_eq!(
+            response_event(&none, &granted(8080, "SUCCESS"), None, success).map(|e| e.reason),
+            None
+        );
+        assert_eq!(
+            reason(&none, &granted(8080, "SUCCESS"), success).as_deref(),
+            Some(event::reason::EXTERNAL_PORT_MISMATCH)
+        );
+        // Reported once, not on every renewal.
+        assert_eq!(
+            reason(
+                &granted(8080, "SUCCESS"),
+                &granted(8080, "SUCCESS"),
+                success
+            ),
+            None
+        );
+
+        let failed = crd::PCPMapStatus {
+            last_result_code: Some("NO_RESOURCES".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            reason(&none, &failed, no_resources).as_deref(),
+            Some(event::reason::MAPPING_FAILED)
+        );
+        assert_eq!(reason(&failed, &failed, no_resources), None);
+        assert_eq!(
+            reason(
+                &granted(80, "SUCCESS"),
+                &granted(80, "NO_RESOURCES"),
+                no_resources
+            )
+            .as_deref(),
+            Some(event::reason::RENEWAL_FAILED)
+        );
+        assert_eq!(
+            reason(
+                &none,
+                &granted(80, "CANNOT_PROVIDE_EXTERNAL"),
+                cannot_provide_external
+            )
+            .as_deref(),
+            Some(event::reason::EXTERNAL_PORT_CONFLICT)
+        );
+    }
+}
@@ -1,6 +1,6 @@
 //! CRDs
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use garde::Validate;
 use kube::CustomResource;
@@ -16,35 +16,189 @@ use serde::{Deserialize, Serialize};
     namespaced
 )]
 #[kube(status = "PCPMapStatus")]
+#[serde(rename_all = "camelCase")]
 #[kube(printcolumn = r#"{"name":"From", "jsonPath": ".spec.from", "type": "integer"}"#)]
 #[kube(printcolumn = r#"{"name":"To", "jsonPath": ".spec.to", "type": "string"}"#)]
+#[kube(
+    printcolumn = r#"{"name":"External", "jsonPath": ".status.external_endpoint", "type": "string"}"#
+)]
+#[kube(
+    printcolumn = r#"{"name":"Ready", "jsonPath": ".status.conditions[?(@.type==\"Ready\")].status", "type": "string"}"#
+)]
 pub struct PCPMapSpec {
     /// The protocol to forward.
     #[garde(skip)] // TODO: #[garde(dive)]
     pub protocol: Protocol,
 
     /// The port number to forward from.
+    ///
+    /// Any port the PCP server assigns is accepted if unset, see the assigned port
+    /// at the status.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     #[garde(skip)] // TODO: #[garde(dive)]
-    pub from: PortNumber,
+    pub from: Option<PortNumber>,
+
+    /// Only suggest the `from` port to the PCP server, and accept any other port it
+    /// assigns instead of failing the mapping.
+    #[serde(default)]
+    #[garde(skip)]
+    pub suggest_only: bool,
 
     /// The address to forward to.
     #[garde(skip)] // TODO: #[garde(dive)]
     pub to: SocketAddr,
+
+    /// The remote address prefixes in CIDR notation (like `203.0.113.0/24`) that are allowed
+    /// to reach the forwarded port.
+    ///
+    /// Everyone is allowed if empty.
+    #[serde(default)]
+    #[garde(skip)]
+    pub allowed_sources: Vec<String>,
+
+    /// Request the mapping on behalf of the `to` address, which is not the address
+    /// of the controller itself.
+    ///
+    /// Requires the PCP server to authorize the controller to do so.
+    #[serde(default)]
+    #[garde(skip)]
+    pub third_party: bool,
+
+    /// The name of the [`PCPGateway`] to request the mapping at.
+    ///
+    /// The gateway the controller is configured with is used if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[garde(skip)]
+    pub gateway_ref: Option<String>,
+}
+
+/// A definition of the [`PCPGateway`] custom resource.
+///
+/// Describes a PCP server to request the mappings at, in addition to the one the controller
+/// is configured with.
+///
+/// The unsolicited `ANNOUNCE`s of these servers are not received, as they are sent to the PCP
+/// client port taken by the configured gateway. The mappings are requested again once the server
+/// restart is detected from the epoch of the next response, that is at the next renewal at most.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, Validate, JsonSchema)]
+#[kube(group = "port-forward.io", version = "v1alpha1", kind = "PCPGateway")]
+#[serde(rename_all = "camelCase")]
+#[kube(printcolumn = r#"{"name":"Address", "jsonPath": ".spec.address", "type": "string"}"#)]
+pub struct PCPGatewaySpec {
+    /// The address of the PCP server.
+    #[garde(skip)] // TODO: #[garde(dive)]
+    pub address: SocketAddr,
 }
 
 /// A definition of the status for the [`PCPMap`] custom resource.
 #[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct PCPMapStatus {
     /// The effective protocol number.
+    // The fields that predate the camelCase naming keep their names, so that the existing
+    // objects do not lose them.
+    #[serde(rename = "protocol_number")]
     pub protocol_number: Option<ProtocolNumber>,
 
     /// The effective Internal IP to direct the traffic to.
+    #[serde(rename = "internal_ip")]
     pub internal_ip: Option<String>,
 
     /// The endpoint to reach the forwarded port from the outside.
+    #[serde(rename = "external_endpoint")]
     pub external_endpoint: Option<SocketAddr>,
+
+    /// The external port the PCP server has assigned.
+    pub assigned_port: Option<PortNumber>,
+
+    /// The last time the PCP server was detected to have lost its mappings state.
+    pub last_server_state_loss: Option<Time>,
+
+    /// The name of the result code of the last PCP server response.
+    pub last_result_code: Option<String>,
+
+    /// The lifetime (in seconds) the PCP server has granted to the mapping.
+    pub granted_lifetime: Option<u32>,
+
+    /// The generation of the resource the mapping at the PCP server is effective for.
+    pub observed_generation: Option<i64>,
+
+    /// The mapping the controller has requested at the PCP server.
+    ///
+    /// Only set by the reconciler, so it is left out of the status updates otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied_mapping: Option<AppliedMapping>,
+
+    /// The conditions of the mapping.
+    ///
+    /// See [`condition`] for the condition types.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// The mapping the controller has requested for the [`PCPMap`].
+///
+/// Tracked to remove the mapping from the PCP server once the spec changes
+/// identify a different one.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedMapping {
+    /// The protocol number of the mapping.
+    pub protocol_number: ProtocolNumber,
+
+    /// The internal IP of the mapping.
+    pub internal_ip: IpAddr,
+
+    /// The internal port of the mapping.
+    pub internal_port: PortNumber,
+
+    /// The name of the [`PCPGateway`] the mapping was requested at.
+    ///
+    /// Unset for the gateway the controller is configured with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway_ref: Option<String>,
+
+    /// The generation of the resource the mapping was requested for.
+    pub generation: Option<i64>,
 }
 
+/// The condition types of the [`PCPMapStatus`].
+pub mod condition {
+    /// The mapping is established at the PCP server.
+    pub const READY: &str = "Ready";
+
+    /// The PCP server has responded with an error.
+    pub const DEGRADED: &str = "Degraded";
+
+    /// The mapping could not get the requested external endpoint.
+    pub const CONFLICT: &str = "Conflict";
+}
+
+/// The annotations used by the controller.
+pub mod annotation {
+    /// The mapping nonce, as a hex string.
+    ///
+    /// Generated randomly by the controller and kept for the lifetime of the resource.
+    pub const NONCE: &str = "port-forward.io/nonce";
+
+    /// The comma-separated list of the ports to expose, like `tcp/25565,udp/25565`.
+    ///
+    /// Set at the `Service` or `Pod` to have the [`PCPMap`](super::PCPMap)s generated for it.
+    pub const EXPOSE: &str = "port-forward.io/expose";
+}
+
+/// The labels used by the controller.
+pub mod label {
+    /// The UID of the resource the [`PCPMap`](super::PCPMap) was generated for.
+    pub const OWNER_UID: &str = "port-forward.io/owner-uid";
+}
+
+/// A Kubernetes-style status condition.
+pub type Condition = k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+
+/// A timestamp.
+pub type Time = k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
 /// A port number.
 pub type PortNumber = u16;
 
@@ -3,5 +3,10 @@
 use kube::CustomResourceExt;
 
 fn main() {
-    print!("{}", serde_yaml::to_string(&crd::PCPMap::crd()).unwrap())
+    print!("{}", serde_yaml::to_string(&crd::PCPMap::crd()).unwrap());
+    println!("---");
+    print!(
+        "{}",
+        serde_yaml::to_string(&crd::PCPGateway::crd()).unwrap()
+    );
 }
@@ -0,0 +1,386 @@
+//! Leader election over the `coordination.k8s.io` `Lease`.
+//!
+//! The replica holding the lease is the leader. The leader renews the lease periodically,
+//! and the others take it over once it has not been renewed for the lease duration.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use futures::Stream;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+};
+
+/// The leader election parameters.
+#[derive(Debug, Clone)]
+pub struct Params {
+    /// The name of the lease.
+    pub lease_name: String,
+
+    /// The identity of this replica.
+    pub identity: String,
+
+    /// The time after the last renewal at which the others can take the lease over.
+    pub lease_duration: Duration,
+
+    /// The time after the last renewal at which the leader gives up the leadership
+    /// if it still fails to renew the lease.
+    ///
+    /// Must be less than the lease duration, so that the leader steps down before
+    /// the others take over.
+    pub renew_deadline: Duration,
+
+    /// The interval between the attempts to acquire or renew the lease.
+    pub retry_period: Duration,
+}
+
+impl Params {
+    /// Create new [`Params`] with the default timings.
+    pub fn new(lease_name: String, identity: String) -> Self {
+        Self {
+            lease_name,
+            identity,
+            lease_duration: Duration::from_secs(15),
+            renew_deadline: Duration::from_secs(10),
+            retry_period: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The leadership state of this replica.
+#[derive(Debug, Clone)]
+pub struct Leadership {
+    /// Whether this replica is the leader.
+    rx: tokio::sync::watch::Receiver<bool>,
+}
+
+impl Leadership {
+    /// The leadership of the only replica, which is always the leader.
+    pub fn always() -> Self {
+        let (_, rx) = tokio::sync::watch::channel(true);
+        Self { rx }
+    }
+
+    /// Check if this replica is the leader.
+    pub fn is_leader(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Wait until this replica becomes the leader.
+    ///
+    /// Never completes if the election has stopped before that.
+    pub async fn acquired(&self) {
+        let mut rx = self.rx.clone();
+        if rx.wait_for(|is_leader| *is_leader).await.is_err() {
+            std::future::pending().await
+        }
+    }
+
+    /// Wait until this replica is no longer the leader.
+    ///
+    /// Never completes if the election has stopped while this replica is the leader.
+    pub async fn lost(&self) {
+        let mut rx = self.rx.clone();
+        if rx.wait_for(|is_leader| !*is_leader).await.is_err() {
+            std::future::pending().await
+        }
+    }
+
+    /// A stream yielding every time this replica becomes the leader.
+    pub fn transitions(&self) -> impl Stream<Item = ()> + Send + Sync + 'static {
+        futures::stream::unfold(self.clone(), |leadership| async move {
+            leadership.lost().await;
+            leadership.acquired().await;
+            Some(((), leadership))
+        })
+    }
+}
+
+/// The lease record as it was last observed.
+#[derive(Debug)]
+struct Observed {
+    /// The holder of the lease.
+    holder: Option<String>,
+
+    /// The time the holder has last renewed the lease at.
+    renew_time: Option<MicroTime>,
+
+    /// The local time the record has changed at.
+    ///
+    /// Used instead of the renewal time to avoid depending on the clock skew
+    /// between the replicas.
+    changed_at: Instant,
+}
+
+/// What to do with the lease.
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    /// Take the lease over.
+    Acquire,
+
+    /// Renew the lease held by us.
+    Renew,
+
+    /// Wait for the lease held by the other replica to expire.
+    Wait(Duration),
+}
+
+/// The leader elector.
+#[derive(Debug)]
+pub struct Elector {
+    /// The leases API.
+    api: kube::Api<Lease>,
+
+    /// The election parameters.
+    params: Params,
+
+    /// The lease record as it was last observed.
+    observed: Option<Observed>,
+
+    /// Publishes the leadership state.
+    tx: tokio::sync::watch::Sender<bool>,
+}
+
+impl Elector {
+    /// Create a new [`Elector`] for the lease at the given API.
+    pub fn new(api: kube::Api<Lease>, params: Params) -> (Self, Leadership) {
+        let (tx, rx) = tokio::sync::watch::channel(false);
+        let elector = Self {
+            api,
+            params,
+            observed: None,
+            tx,
+        };
+        (elector, Leadership { rx })
+    }
+
+    /// Run the election until the stop future completes.
+    ///
+    /// Once the leadership is lost, this replica stands by and contends for the lease again.
+    /// The lease is released on stop, so that the others can take over right away.
+    pub async fn run(mut self, stop: impl Future<Output = ()>) {
+        let mut stop = std::pin::pin!(stop);
+        let mut renewed_at = None;
+
+        loop {
+            let now = Instant::now();
+            let next_attempt_in = match self.try_acquire_or_renew(now).await {
+                Ok(None) => {
+                    if renewed_at.is_none() {
+                        tracing::info!(message = "acquired leadership", identity = %self.params.identity);
+                        self.tx.send_replace(true);
+                    }
+                    renewed_at = Some(now);
+                    self.params.retry_period
+                }
+                Ok(Some(wait)) if renewed_at.is_some() => {
+                    tracing::error!(message = "lease taken over", identity = %self.params.identity);
+                    renewed_at = None;
+                    self.tx.send_replace(false);
+                    wait
+                }
+                Ok(Some(wait)) => wait,
+                Err(error) => {
+                    tracing::warn!(message = "unable to acquire or renew the lease", ?error);
+                    self.params.retry_period
+                }
+            };
+
+            if renewed_at.is_some_and(|renewed_at| {
+                now.duration_since(renewed_at) >= self.params.renew_deadline
+            }) {
+                tracing::error!(message = "lost leadership", identity = %self.params.identity);
+                renewed_at = None;
+                self.tx.send_replace(false);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(next_attempt_in) => {}
+                _ = &mut stop => break,
+            }
+        }
+
+        if renewed_at.is_some() {
+            if let Err(error) = self.release().await {
+                tracing::warn!(message = "unable to release the lease", ?error);
+            }
+            self.tx.send_replace(false);
+        }
+    }
+
+    /// Try to acquire or renew the lease.
+    ///
+    /// Returns [`None`] if we hold the lease, or the time to wait before the next attempt.
+    async fn try_acquire_or_renew(
+        &mut self,
+        now: Instant,
+    ) -> Result<Option<Duration>, kube::Error> {
+        let Some(mut lease) = self.api.get_opt(&self.params.lease_name).await? else {
+            let lease = Lease {
+                metadata: kube::api::ObjectMeta {
+                    name: Some(self.params.lease_name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(self.held_spec(None, true)),
+            };
+            self.api
+                .create(&kube::api::PostParams::default(), &lease)
+                .await?;
+            return Ok(None);
+        };
+
+        let acquire = match decide(&mut self.observed, &self.params, lease.spec.as_ref(), now) {
+            Decision::Wait(wait) => return Ok(Some(wait)),
+            Decision::Acquire => true,
+            Decision::Renew => false,
+        };
+
+        // The resource version in the metadata guards against the concurrent updates.
+        lease.spec = Some(self.held_spec(lease.spec.take(), acquire));
+        self.api
+            .replace(
+                &self.params.lease_name,
+                &kube::api::PostParams::default(),
+                &lease,
+            )
+            .await?;
+
+        Ok(None)
+    }
+
+    /// Release the lease if we hold it.
+    async fn release(&self) -> Result<(), kube::Error> {
+        let Some(mut lease) = self.api.get_opt(&self.params.lease_name).await? else {
+            return Ok(());
+        };
+        let Some(spec) = &mut lease.spec else {
+            return Ok(());
+        };
+        if spec.holder_identity.as_ref() != Some(&self.params.identity) {
+            return Ok(());
+        }
+
+        spec.holder_identity = None;
+        self.api
+            .replace(
+                &self.params.lease_name,
+                &kube::api::PostParams::default(),
+                &lease,
+            )
+            .await?;
+
+        tracing::info!(message = "released the lease", identity = %self.params.identity);
+        Ok(())
+    }
+
+    /// The lease spec with us as the holder.
+    fn held_spec(&self, spec: Option<LeaseSpec>, acquire: bool) -> LeaseSpec {
+        let spec = spec.unwrap_or_default();
+        let now = MicroTime(k8s_openapi::chrono::Utc::now());
+        let lease_duration_seconds = self.params.lease_duration.as_secs().try_into().ok();
+
+        if !acquire {
+            return LeaseSpec {
+                renew_time: Some(now),
+                lease_duration_seconds,
+                ..spec
+            };
+        }
+
+        LeaseSpec {
+            holder_identity: Some(self.params.identity.clone()),
+            acquire_time: Some(now.clone()),
+            renew_time: Some(now),
+            lease_duration_seconds,
+            lease_transitions: Some(spec.lease_transitions.unwrap_or_default() + 1),
+        }
+    }
+}
+
+/// Decide what to do with the lease.
+fn decide(
+    observed: &mut Option<Observed>,
+    params: &Params,
+    spec: Option<&LeaseSpec>,
+    now: Instant,
+) -> Decision {
+    let holder = spec.and_then(|spec| spec.holder_identity.clone());
+    let renew_time = spec.and_then(|spec| spec.renew_time.clone());
+
+    match holder.as_deref() {
+        None | Some("") => return Decision::Acquire,
+        Some(holder) if holder == params.identity => return Decision::Renew,
+        Some(_) => {}
+    }
+
+    let changed = observed.as_ref().map_or(true, |observed| {
+        observed.holder != holder || observed.renew_time != renew_time
+    });
+    if changed {
+        *observed = Some(Observed {
+            holder,
+            renew_time,
+            changed_at: now,
+        });
+    }
+
+    let changed_at = observed
+        .as_ref()
+        .map_or(now, |observed| observed.changed_at);
+    let expires_at = changed_at + params.lease_duration;
+    if expires_at <= now {
+        return Decision::Acquire;
+    }
+
+    Decision::Wait(expires_at.duration_since(now).min(params.retry_period))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let params = Params::new("lease".to_owned(), "me".to_owned());
+        let mut observed = None;
+
+        let spec = |holder: &str, renew_secs: i64| LeaseSpec {
+            holder_identity: Some(holder.to_owned()),
+            renew_time: Some(MicroTime(
+                k8s_openapi::chrono::DateTime::from_timestamp(renew_secs, 0).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let mut decide = |spec: Option<&LeaseSpec>, now| decide(&mut observed, &params, spec, now);
+
+        let now = Instant::now();
+
+        assert_eq!(decide(None, now), Decision::Acquire);
+        assert_eq!(decide(Some(&spec("me", 0)), now), Decision::Renew);
+        assert_eq!(
+            decide(Some(&spec("other", 0)), now),
+            Decision::Wait(params.retry_period)
+        );
+
+        // The lease keeps being renewed.
+        let now = now + params.lease_duration;
+        assert_eq!(
+            decide(Some(&spec("other", 15)), now),
+            Decision::Wait(params.retry_period)
+        );
+
+        // The renewals stop.
+        let later = now + params.lease_duration - Duration::from_secs(1);
+        assert_eq!(
+            decide(Some(&spec("other", 15)), later),
+            Decision::Wait(Duration::from_secs(1))
+        );
+        assert_eq!(
+            decide(Some(&spec("other", 15)), now + params.lease_duration),
+            Decision::Acquire
+        );
+    }
+}
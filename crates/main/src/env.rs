@@ -19,3 +19,30 @@ impl PcpServerAddress {
         }
     }
 }
+
+/// What to do with the mappings at the PCP server on shutdown.
+#[derive(Debug, Clone, Copy)]
+pub enum ShutdownPolicy {
+    /// Leave the mappings to expire, or to be renewed by the next leader.
+    Preserve,
+
+    /// Delete the mappings.
+    Delete,
+}
+
+/// The shutdown policy is not known.
+#[derive(Debug, thiserror::Error)]
+#[error("unknown shutdown policy {0:?}, expected \"preserve\" or \"delete\"")]
+pub struct UnknownShutdownPolicy(String);
+
+impl std::str::FromStr for ShutdownPolicy {
+    type Err = UnknownShutdownPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Self::Preserve),
+            "delete" => Ok(Self::Delete),
+            _ => Err(UnknownShutdownPolicy(s.to_owned())),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! The health and readiness of the controller.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The health and readiness of the controller.
+#[derive(Debug)]
+pub struct Health {
+    /// The essential tasks that have terminated.
+    terminated: Mutex<Vec<&'static str>>,
+
+    /// Whether the status listener indexer is ready.
+    pub indexer_ready: Arc<AtomicBool>,
+
+    /// The health of the PCP client of the default gateway.
+    pub pcp_client: Arc<pcp_client::health::Health>,
+
+    /// The leadership of this replica, the PCP client only runs at the leader.
+    pub leadership: leader_election::Leadership,
+}
+
+impl Health {
+    /// Create a new [`Health`] of the replica with the given leadership.
+    pub fn new(leadership: leader_election::Leadership) -> Self {
+        Self {
+            terminated: Default::default(),
+            indexer_ready: Default::default(),
+            pcp_client: Default::default(),
+            leadership,
+        }
+    }
+
+    /// Run the essential task, recording its termination.
+    ///
+    /// The essential tasks are never supposed to terminate, so the controller
+    /// is unhealthy once any of them does.
+    pub async fn watch(self: Arc<Self>, task: &'static str, fut: impl Future<Output = ()>) {
+        fut.await;
+        tracing::error!(message = "essential task terminated", task);
+        self.terminated.lock().unwrap().push(task);
+    }
+
+    /// Check if the controller is healthy, returning the problem otherwise.
+    pub fn check_health(&self) -> Result<(), String> {
+        let terminated = self.terminated.lock().unwrap();
+        if !terminated.is_empty() {
+            return Err(format!("terminated: {}", terminated.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Check if the controller is ready, returning the reason otherwise.
+    pub fn check_readiness(&self) -> Result<(), String> {
+        self.check_health()?;
+        if !self.indexer_ready.load(Ordering::Relaxed) {
+            return Err("the indexer is not ready".to_owned());
+        }
+        // The standbys are ready to take over without the PCP client running.
+        if self.leadership.is_leader() && !self.pcp_client.has_answered() {
+            return Err("the PCP server has not answered yet".to_owned());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! The HTTP server for the metrics and the probes.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{header, Method, Request, Response, StatusCode};
+
+use crate::health::Health;
+
+/// The metrics exposed over HTTP.
+#[derive(Debug, Default)]
+pub struct Registry {
+    /// The metrics of the controllers.
+    pub controller: Arc<crd_controller::metrics::Metrics>,
+
+    /// The metrics of the PCP clients, by the PCP server address.
+    ///
+    /// The clients that are gone are skipped.
+    pub clients: Mutex<Vec<(SocketAddr, Weak<pcp_client::metrics::Metrics>)>>,
+}
+
+impl Registry {
+    /// Add the metrics of the PCP client.
+    pub fn register_client(
+        &self,
+        server_address: SocketAddr,
+        metrics: &Arc<pcp_client::metrics::Metrics>,
+    ) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|(_, metrics)| metrics.strong_count() > 0);
+        clients.push((server_address, Arc::downgrade(metrics)));
+    }
+
+    /// Encode all of the metrics.
+    pub fn encode(&self) -> String {
+        let clients: Vec<_> = {
+            let clients = self.clients.lock().unwrap();
+            clients
+                .iter()
+                .filter_map(|(server_address, metrics)| {
+                    Some((server_address.to_string(), metrics.upgrade()?))
+                })
+                .collect()
+        };
+        let clients: Vec<_> = clients
+            .iter()
+            .map(|(server_address, metrics)| (server_address.clone(), metrics.as_ref()))
+            .collect();
+
+        let mut encoder = metrics::Encoder::default();
+        metrics::Encode::encode(self.controller.as_ref(), &mut encoder);
+        pcp_client::metrics::encode(&mut encoder, &clients);
+        encoder.finish()
+    }
+}
+
+/// Serve the HTTP requests until accepting the connections fails.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    registry: Arc<Registry>,
+    health: Arc<Health>,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, remote_address) = listener.accept().await?;
+
+        let registry = Arc::clone(&registry);
+        let health = Arc::clone(&health);
+        let service = hyper::service::service_fn(move |request| {
+            let response = handle(&registry, &health, &request);
+            async move { Ok::<_, Infallible>(response) }
+        });
+
+        tokio::spawn(async move {
+            let io = hyper_util::rt::TokioIo::new(stream);
+            if let Err(error) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                tracing::debug!(message = "HTTP connection error", ?error, %remote_address);
+            }
+        });
+    }
+}
+
+/// Handle the HTTP request.
+fn handle<Body>(
+    registry: &Registry,
+    health: &Health,
+    request: &Request<Body>,
+) -> Response<Full<Bytes>> {
+    let response = Response::builder();
+
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => response
+            .header(header::CONTENT_TYPE, metrics::CONTENT_TYPE)
+            .body(Full::from(registry.encode())),
+        (&Method::GET, "/healthz") => probe(response, health.check_health()),
+        (&Method::GET, "/readyz") => probe(response, health.check_readiness()),
+        _ => response.status(StatusCode::NOT_FOUND).body(Full::default()),
+    };
+
+    response.expect("the response parts are valid")
+}
+
+/// Respond to the probe with the check result.
+fn probe(
+    response: hyper::http::response::Builder,
+    result: Result<(), String>,
+) -> hyper::http::Result<Response<Full<Bytes>>> {
+    let (status, body) = match result {
+        Ok(()) => (StatusCode::OK, "ok".to_owned()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    };
+    response
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Full::from(body))
+}
@@ -1,11 +1,14 @@
 //! Main entrypoint.
 
 mod env;
+mod health;
+mod http;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use color_eyre::eyre::OptionExt;
 use env::PcpServerAddress;
+use futures::FutureExt as _;
 
 #[tokio::main]
 async fn main() -> Result<(), color_eyre::Report> {
@@ -14,86 +17,242 @@ async fn main() -> Result<(), color_eyre::Report> {
 
     let bind_ip_address: std::net::IpAddr =
         envfury::or("BIND_ADDR", std::net::Ipv6Addr::UNSPECIFIED.into())?;
+    // Binding to the PCP client listen port on an unspecified address lets us receive the
+    // unsolicited `ANNOUNCE` packets the server multicasts after a restart.
     let bind_port: u16 = envfury::or("BIND_PORT", pcp_consts::PCP_CLIENT_LISTEN_PORT)?;
     let bind_socket_address = std::net::SocketAddr::new(bind_ip_address, bind_port);
 
-    let local_ip_address: std::net::IpAddr = envfury::must("LOCAL_ADDR")?;
+    // Detected from the received packets and the routing table if unset.
+    let local_ip_address: Option<std::net::IpAddr> = envfury::maybe("LOCAL_ADDR")?;
 
     let pcp_server_address = PcpServerAddress::from_env()?;
 
-    let keepalive_interval_secs = envfury::or("KEEPALIVE_INTERVAL_SECS", 30)?;
-    let keepalive_interval = std::time::Duration::from_secs(keepalive_interval_secs);
+    // The lifetime we request, the renewals are scheduled based on the lifetime
+    // the server actually grants.
+    let mapping_lifetime: u32 = envfury::or("MAPPING_LIFETIME_SECS", 3600)?;
+
+    let load_balancer_class: String = envfury::or(
+        "LOAD_BALANCER_CLASS",
+        crd_controller::service::DEFAULT_LOAD_BALANCER_CLASS.to_owned(),
+    )?;
+
+    // Watching all the pods is expensive, so exposing their host ports is opt-in.
+    let expose_pods: bool = envfury::or("EXPOSE_PODS", false)?;
+
+    let http_address: std::net::SocketAddr =
+        envfury::or("HTTP_ADDR", (std::net::Ipv6Addr::UNSPECIFIED, 8080).into())?;
+
+    // Only needed to run multiple replicas, the single one is always the leader otherwise.
+    let leader_election_namespace: Option<String> = envfury::maybe("LEADER_ELECTION_NAMESPACE")?;
+    let leader_election_lease: String = envfury::or(
+        "LEADER_ELECTION_LEASE",
+        "port-forward-controller".to_owned(),
+    )?;
+
+    // Whether to delete the mappings at the PCP server on shutdown, or leave them to expire.
+    let shutdown_policy: env::ShutdownPolicy =
+        envfury::or("SHUTDOWN_POLICY", env::ShutdownPolicy::Preserve)?;
+    // How long to wait for the server to acknowledge the deletions.
+    let shutdown_timeout = Duration::from_secs(envfury::or("SHUTDOWN_TIMEOUT_SECS", 10)?);
 
     // ---
 
     let pcp_server_address = match pcp_server_address {
         env::PcpServerAddress::Explicit(socket_address) => socket_address,
         env::PcpServerAddress::PortOnly(port) => {
-            let maybe_ip_address = route::gateway_for(local_ip_address).await?;
+            let interface = local_ip_address.unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into());
+            let maybe_ip_address = route::gateway_for(interface).await?;
             let ip_address = maybe_ip_address
                 .ok_or_eyre("unable to detect PCP server IP address, specify it manually")?;
             std::net::SocketAddr::new(ip_address, port)
         }
     };
 
-    let pcp_client_socket = tokio::net::UdpSocket::bind(bind_socket_address).await?;
-
-    let local_socket_address = {
-        let effective_socket_addr = pcp_client_socket.local_addr()?;
-        std::net::SocketAddr::new(local_ip_address, effective_socket_addr.port())
+    let client_ip_address = match local_ip_address {
+        Some(val) => val,
+        None => source_ip_address_for(pcp_server_address)?,
     };
-    tracing::info!(message = "PCP client local address", %local_socket_address);
+    tracing::info!(message = "PCP client IP address", %client_ip_address);
 
-    let kube_client = kube::Client::try_default().await?;
+    let http_listener = tokio::net::TcpListener::bind(http_address).await?;
 
-    let (notifications_tx, notifications_rx) = tokio::sync::mpsc::channel(0xff);
+    let metrics_registry = Arc::new(http::Registry::default());
 
-    let pcp_client_transport = pcp_client_tokio::Transport {
-        socket: pcp_client_socket,
-        local_address: local_socket_address,
-    };
-    let pcp_client = pcp_client::Client {
-        runtime: pcp_client_tokio::Runtime,
-        transport: pcp_client_transport,
-        server_address: pcp_server_address,
-        mappings: Default::default(),
-        keepalive_interval,
-        notifications_tx,
+    let kube_client = kube::Client::try_default().await?;
+
+    let (elector, leadership) = match leader_election_namespace {
+        Some(namespace) => {
+            let identity: String = envfury::must("LEADER_ELECTION_IDENTITY")?;
+            let (elector, leadership) = leader_election::Elector::new(
+                kube::Api::namespaced(kube_client.clone(), &namespace),
+                leader_election::Params::new(leader_election_lease, identity),
+            );
+            (Some(elector), leadership)
+        }
+        None => (None, leader_election::Leadership::always()),
     };
 
+    let health = Arc::new(health::Health::new(leadership.clone()));
+
+    let (notifications_tx, notifications_rx) = tokio::sync::mpsc::channel(0xff);
+
     let (command_tx, command_rx) = tokio::sync::mpsc::channel(1);
 
-    let mapping_lifetime = keepalive_interval
-        .as_secs()
-        .try_into()
-        .unwrap_or(u32::MAX)
-        .saturating_mul(2);
+    // The controllers are stopped first, so that the clients get all of their commands.
+    let (controllers_shutdown_tx, controllers_shutdown_rx) = tokio_signal::channel();
+    let controllers_shutdown = controllers_shutdown_rx.shared();
+    let (clients_shutdown_tx, clients_shutdown_rx) = tokio_signal::channel();
+    let clients_shutdown = clients_shutdown_rx.shared();
+
+    // Every PCP client holds a sender, so the channel closes once all of them are done.
+    let (pcp_clients_tx, mut pcp_clients_rx) = tokio::sync::mpsc::channel::<()>(1);
+
+    // The clients of the additional gateways listen at the ephemeral ports, as the PCP client
+    // listen port is taken by the default one. So they miss the unsolicited `ANNOUNCE`s sent
+    // to that port on the server restarts, and only detect those from the epoch of the next
+    // response.
+    let gateway_spawner: crd_controller::gateway::Spawner = Arc::new({
+        let notifications_tx = notifications_tx.clone();
+        let metrics_registry = Arc::clone(&metrics_registry);
+        let clients_shutdown = clients_shutdown.clone();
+        let pcp_clients_tx = pcp_clients_tx.clone();
+        let leadership = leadership.clone();
+        move |server_address| {
+            let notifications_tx = notifications_tx.clone();
+            let metrics_registry = Arc::clone(&metrics_registry);
+            let clients_shutdown = clients_shutdown.clone();
+            let pcp_clients_tx = pcp_clients_tx.clone();
+            let leadership = leadership.clone();
+            Box::pin(async move {
+                let socket =
+                    tokio::net::UdpSocket::bind(std::net::SocketAddr::new(bind_ip_address, 0))
+                        .await?;
+                let pcp_client = new_pcp_client(
+                    socket,
+                    local_ip_address,
+                    client_ip_address,
+                    server_address,
+                    notifications_tx,
+                    &metrics_registry,
+                    Default::default(),
+                )?;
+
+                let (command_tx, mut command_rx) = tokio::sync::mpsc::channel(1);
+                // The new leader takes the mappings over, see the default PCP client below.
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = run_pcp_client(
+                            pcp_client,
+                            &mut command_rx,
+                            clients_shutdown,
+                            shutdown_policy,
+                            shutdown_timeout,
+                        ) => {}
+                        _ = leadership.lost() => {}
+                    }
+                    drop(pcp_clients_tx);
+                });
+
+                Ok(command_tx)
+            })
+        }
+    });
+
+    let gateways = Arc::new(crd_controller::gateway::Gateways::new(
+        crd_controller::gateway::Gateway::new(command_tx.clone()),
+    ));
 
     let converter = crd_controller::pcp::Converter {
-        nonce: [0; 12],
         lifetime: mapping_lifetime,
+        client_ip_address: Some(pcp_ip_conv::unify(client_ip_address)),
+    };
+
+    let service_ctx = crd_controller::service::Context {
+        params: crd_controller::reconciler::Params::default(),
+        load_balancer_class: load_balancer_class.into(),
+        gateways: Arc::clone(&gateways),
+        k8s_client: kube_client.clone(),
+        reporter: "port-forward-controller".into(),
+        converter: converter.clone(),
+        applied: Default::default(),
+        metrics: Arc::clone(&metrics_registry.controller),
+        leadership: leadership.clone(),
+    };
+    let service_ctx = Arc::new(service_ctx);
+
+    let expose_ctx = crd_controller::expose::Context {
+        params: crd_controller::reconciler::Params::default(),
+        k8s_client: kube_client.clone(),
+        client_ip_address: converter.client_ip_address,
+        metrics: Arc::clone(&metrics_registry.controller),
+        leadership: leadership.clone(),
     };
+    let expose_ctx = Arc::new(expose_ctx);
 
     let reconciler_ctx = crd_controller::reconciler::Context {
         params: crd_controller::reconciler::Params::default(),
-        command_tx,
+        gateways: Arc::clone(&gateways),
         k8s_client: kube_client.clone(),
         converter: converter.clone(),
+        reporter: "port-forward-controller".into(),
+        applied: Default::default(),
+        metrics: Arc::clone(&metrics_registry.controller),
+        leadership: leadership.clone(),
     };
     let reconciler_ctx = Arc::new(reconciler_ctx);
 
     let all_crds_api = kube::Api::all(kube_client.clone());
 
+    // The gateway changes move the mappings to the new PCP clients right away.
+    let (pcpmap_requeue_tx, pcpmap_requeue_rx) = futures::channel::mpsc::unbounded();
     let controller = kube::runtime::Controller::new(
         all_crds_api.clone(),
         kube::runtime::watcher::Config::default(),
+    )
+    .reconcile_on(pcpmap_requeue_rx);
+
+    let gateway_ctx = crd_controller::gateway::Context {
+        params: crd_controller::reconciler::Params::default(),
+        gateways,
+        spawner: gateway_spawner,
+        k8s_client: kube_client.clone(),
+        pcpmaps: controller.store(),
+        pcpmap_requeue_tx,
+        metrics: Arc::clone(&metrics_registry.controller),
+        leadership: leadership.clone(),
+    };
+    let gateway_ctx = Arc::new(gateway_ctx);
+
+    let gateway_controller = kube::runtime::Controller::new(
+        kube::Api::<crd::PCPGateway>::all(kube_client.clone()),
+        kube::runtime::watcher::Config::default(),
     );
 
+    let service_controller = kube::runtime::Controller::new(
+        kube::Api::<k8s_openapi::api::core::v1::Service>::all(kube_client.clone()),
+        kube::runtime::watcher::Config::default(),
+    );
+
+    let expose_services_controller = kube::runtime::Controller::new(
+        kube::Api::<k8s_openapi::api::core::v1::Service>::all(kube_client.clone()),
+        kube::runtime::watcher::Config::default(),
+    );
+
+    let expose_pods_controller = expose_pods.then(|| {
+        kube::runtime::Controller::new(
+            kube::Api::<k8s_openapi::api::core::v1::Pod>::all(kube_client.clone()),
+            kube::runtime::watcher::Config::default(),
+        )
+    });
+
     let indexer = crd_controller::status::indexer::new(converter);
 
     let status_listener = crd_controller::status::Listener {
         indexer,
         kube_client,
+        metrics: Arc::clone(&metrics_registry.controller),
+        reporter: "port-forward-controller".into(),
+        indexer_ready: Arc::clone(&health.indexer_ready),
     };
 
     use kube::runtime::WatchStreamExt;
@@ -103,15 +262,238 @@ async fn main() -> Result<(), color_eyre::Report> {
 
     // ---
 
-    tokio::spawn(crd_controller::run(controller, reconciler_ctx));
+    // The PCP client only runs at the leader, as the replicas would fight over the mappings
+    // otherwise. A new client is started for every leadership term, and the reconcilers
+    // send it all of the mappings once the leadership is acquired.
+    let (leader_stop_tx, leader_stop_rx) = tokio_signal::channel();
+    let pcp_client_task = {
+        let leadership = leadership.clone();
+        let pcp_client_health = Arc::clone(&health.pcp_client);
+        let metrics_registry = Arc::clone(&metrics_registry);
+        let clients_shutdown = clients_shutdown.clone();
+        let mut command_rx = command_rx;
+        async move {
+            loop {
+                tokio::select! {
+                    _ = leadership.acquired() => {}
+                    _ = clients_shutdown.clone() => break,
+                }
+
+                let pcp_client_socket = tokio::net::UdpSocket::bind(bind_socket_address).await?;
+                let pcp_client = new_pcp_client(
+                    pcp_client_socket,
+                    local_ip_address,
+                    client_ip_address,
+                    pcp_server_address,
+                    notifications_tx.clone(),
+                    &metrics_registry,
+                    Arc::clone(&pcp_client_health),
+                )?;
+
+                tokio::select! {
+                    _ = run_pcp_client(
+                        pcp_client,
+                        &mut command_rx,
+                        clients_shutdown.clone(),
+                        shutdown_policy,
+                        shutdown_timeout,
+                    ) => break,
+                    _ = leadership.lost() => {
+                        tracing::info!(message = "stopped the PCP client for the new leader");
+                    }
+                }
+            }
+
+            // Hand the leadership over once we are done.
+            leader_stop_tx.send();
+            drop(pcp_clients_tx);
+
+            Ok::<_, std::io::Error>(())
+        }
+    };
+
+    tokio::spawn(Arc::clone(&health).watch("pcp-client", async move {
+        if let Err(error) = pcp_client_task.await {
+            tracing::error!(message = "unable to start the PCP client", ?error);
+        }
+    }));
+
+    let elector_task = elector.map(|elector| {
+        tokio::spawn(Arc::clone(&health).watch("leader-election", elector.run(leader_stop_rx)))
+    });
 
-    tokio::spawn(status_listener.lifecycle_loop(all_crds_watch, notifications_rx));
+    let mut controller_tasks = vec![
+        tokio::spawn(Arc::clone(&health).watch(
+            "pcpmap-controller",
+            crd_controller::run(
+                controller.graceful_shutdown_on(controllers_shutdown.clone()),
+                reconciler_ctx,
+            ),
+        )),
+        tokio::spawn(Arc::clone(&health).watch(
+            "pcpgateway-controller",
+            crd_controller::gateway::run(
+                gateway_controller.graceful_shutdown_on(controllers_shutdown.clone()),
+                gateway_ctx,
+            ),
+        )),
+        tokio::spawn(Arc::clone(&health).watch(
+            "service-controller",
+            crd_controller::service::run(
+                service_controller.graceful_shutdown_on(controllers_shutdown.clone()),
+                service_ctx,
+            ),
+        )),
+        tokio::spawn(Arc::clone(&health).watch(
+            "expose-services-controller",
+            crd_controller::expose::run(
+                expose_services_controller.graceful_shutdown_on(controllers_shutdown.clone()),
+                Arc::clone(&expose_ctx),
+            ),
+        )),
+    ];
+
+    if let Some(expose_pods_controller) = expose_pods_controller {
+        controller_tasks.push(tokio::spawn(Arc::clone(&health).watch(
+            "expose-pods-controller",
+            crd_controller::expose::run(
+                expose_pods_controller.graceful_shutdown_on(controllers_shutdown),
+                expose_ctx,
+            ),
+        )));
+    }
+
+    // Keeps running through the shutdown to record the released mappings.
+    tokio::spawn(Arc::clone(&health).watch(
+        "status-listener",
+        status_listener.lifecycle_loop(all_crds_watch, notifications_rx),
+    ));
+
+    // Keep serving the probes even if the essential tasks terminate, so that they report it.
+    tokio::spawn(Arc::clone(&health).watch("http-server", {
+        let health = Arc::clone(&health);
+        async move {
+            if let Err(error) = http::serve(http_listener, metrics_registry, health).await {
+                tracing::error!(message = "HTTP server failed", ?error);
+            }
+        }
+    }));
 
     tracing::info!(message = "startup complete");
 
-    // FIXME: this one should actually be running in the spawn as well.
-    // See <https://github.com/rust-lang/rust/issues/96865>.
-    pcp_client.into_lifecycle_loop(command_rx).await;
+    terminated().await?;
+
+    tracing::info!(message = "shutting down");
+
+    controllers_shutdown_tx.send();
+    futures::future::join_all(controller_tasks).await;
+
+    clients_shutdown_tx.send();
+    // The clients might never finish handling the commands if the server is unreachable.
+    let drain_timeout = shutdown_timeout + Duration::from_secs(5);
+    if tokio::time::timeout(drain_timeout, pcp_clients_rx.recv())
+        .await
+        .is_err()
+    {
+        tracing::warn!(message = "timed out waiting for the PCP clients to stop");
+    }
+
+    if let Some(elector_task) = elector_task {
+        let _ = elector_task.await;
+    }
+
+    tracing::info!(message = "shutdown complete");
 
     Ok(())
 }
+
+/// Wait for the termination signal.
+async fn terminated() -> std::io::Result<()> {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        result = tokio::signal::ctrl_c() => result?,
+    }
+
+    Ok(())
+}
+
+/// Run the PCP client until the shutdown, then apply the shutdown policy.
+async fn run_pcp_client(
+    mut pcp_client: pcp_client::Client<pcp_client_tokio::Runtime, pcp_client_tokio::Transport>,
+    command_rx: &mut tokio::sync::mpsc::Receiver<pcp_client::Command>,
+    shutdown: futures::future::Shared<tokio_signal::Receiver>,
+    shutdown_policy: env::ShutdownPolicy,
+    shutdown_timeout: Duration,
+) {
+    pcp_client
+        .lifecycle_loop(command_rx, shutdown.clone())
+        .await;
+
+    // The gateway has been removed or has moved to another address, so the mappings at
+    // this PCP server are no longer needed.
+    let gateway_gone = command_rx.is_closed() && shutdown.peek().is_none();
+
+    match shutdown_policy {
+        _ if gateway_gone => {
+            tracing::info!(message = "the gateway is gone, deleting the mappings");
+            pcp_client.release_all(shutdown_timeout).await;
+        }
+        env::ShutdownPolicy::Preserve => {
+            tracing::info!(message = "leaving the mappings to expire");
+        }
+        env::ShutdownPolicy::Delete => pcp_client.release_all(shutdown_timeout).await,
+    }
+}
+
+/// Create a new PCP client talking to the PCP server over the socket.
+fn new_pcp_client(
+    socket: tokio::net::UdpSocket,
+    local_ip_address: Option<std::net::IpAddr>,
+    client_ip_address: std::net::IpAddr,
+    server_address: std::net::SocketAddr,
+    notifications_tx: tokio::sync::mpsc::Sender<pcp_client::Notification>,
+    metrics_registry: &http::Registry,
+    health: Arc<pcp_client::health::Health>,
+) -> std::io::Result<pcp_client::Client<pcp_client_tokio::Runtime, pcp_client_tokio::Transport>> {
+    let transport = pcp_client_tokio::Transport::new(socket, local_ip_address)?;
+    tracing::info!(
+        message = "PCP client local address",
+        local_address = %transport.local_address(),
+        %server_address,
+    );
+
+    let metrics = Arc::new(pcp_client::metrics::Metrics::default());
+    metrics_registry.register_client(server_address, &metrics);
+
+    Ok(pcp_client::Client {
+        runtime: pcp_client_tokio::Runtime,
+        transport,
+        server_address,
+        mappings: Default::default(),
+        peers: Default::default(),
+        epochs: Default::default(),
+        retransmit: Default::default(),
+        notifications_tx,
+        metrics,
+        health,
+        probe: Some(pcp_client::probe::Probe::new(pcp_ip_conv::unify(
+            client_ip_address,
+        ))),
+    })
+}
+
+/// Detect the IP address the packets to the given address are sent from.
+fn source_ip_address_for(address: std::net::SocketAddr) -> std::io::Result<std::net::IpAddr> {
+    let unspecified: std::net::IpAddr = match address {
+        std::net::SocketAddr::V4(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+        std::net::SocketAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+    };
+
+    // Connecting the UDP socket does not send anything, but makes the system pick
+    // the source address.
+    let socket = std::net::UdpSocket::bind((unspecified, 0))?;
+    socket.connect(address)?;
+    Ok(socket.local_addr()?.ip())
+}
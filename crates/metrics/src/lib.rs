@@ -0,0 +1,306 @@
+//! Minimal metrics primitives with the Prometheus text exposition format encoding.
+//!
+//! See <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// A monotonically increasing counter.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    /// Increment the counter by one.
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    /// Increment the counter by the given amount.
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Get the current value.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    /// Set the value.
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Get the current value.
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A distribution of the observed values over the fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    /// The upper bounds of the buckets, in the ascending order.
+    bounds: &'static [f64],
+
+    /// The state.
+    inner: Mutex<HistogramInner>,
+}
+
+/// The state of the [`Histogram`].
+#[derive(Debug, Clone)]
+struct HistogramInner {
+    /// The non-cumulative count of the observations per bucket, with the `+Inf` one last.
+    buckets: Vec<u64>,
+
+    /// The sum of the observed values.
+    sum: f64,
+}
+
+/// The default buckets for the durations in seconds.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+impl Histogram {
+    /// Create a new [`Histogram`] with the given bucket upper bounds.
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            inner: Mutex::new(HistogramInner {
+                buckets: vec![0; bounds.len() + 1],
+                sum: 0.0,
+            }),
+        }
+    }
+
+    /// Record the observed value.
+    pub fn observe(&self, value: f64) {
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.buckets[index] += 1;
+        inner.sum += value;
+    }
+
+    /// Get the total number of observations.
+    pub fn count(&self) -> u64 {
+        self.inner.lock().unwrap().buckets.iter().sum()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(DURATION_BUCKETS)
+    }
+}
+
+/// A set of metrics of the same kind, distinguished by the labels.
+#[derive(Debug)]
+pub struct Family<Labels, Metric> {
+    /// The metrics by labels.
+    metrics: Mutex<BTreeMap<Labels, Metric>>,
+
+    /// Creates the new metrics.
+    new_metric: fn() -> Metric,
+}
+
+impl<Labels: Ord, Metric: Default> Default for Family<Labels, Metric> {
+    fn default() -> Self {
+        Self::new(Metric::default)
+    }
+}
+
+impl<Labels: Ord, Metric> Family<Labels, Metric> {
+    /// Create a new [`Family`] with the given constructor for the new metrics.
+    pub fn new(new_metric: fn() -> Metric) -> Self {
+        Self {
+            metrics: Default::default(),
+            new_metric,
+        }
+    }
+
+    /// Run the closure on the metric with the given labels, creating it if needed.
+    pub fn with<R>(&self, labels: Labels, f: impl FnOnce(&Metric) -> R) -> R {
+        let mut metrics = self.metrics.lock().unwrap();
+        let metric = metrics.entry(labels).or_insert_with(self.new_metric);
+        f(metric)
+    }
+
+    /// Run the closure on every metric.
+    pub fn for_each(&self, mut f: impl FnMut(&Labels, &Metric)) {
+        let metrics = self.metrics.lock().unwrap();
+        for (labels, metric) in metrics.iter() {
+            f(labels, metric);
+        }
+    }
+}
+
+/// The label names and values.
+pub type Labels<'a> = &'a [(&'a str, &'a str)];
+
+/// The type of a metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// See [`Counter`].
+    Counter,
+
+    /// See [`Gauge`].
+    Gauge,
+
+    /// See [`Histogram`].
+    Histogram,
+}
+
+impl Type {
+    /// The name of the type in the exposition format.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+/// The encoder into the text exposition format.
+///
+/// The samples of a metric must follow its header.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    /// The encoded output.
+    output: String,
+}
+
+impl Encoder {
+    /// Write the metric header.
+    pub fn header(&mut self, name: &str, help: &str, type_: Type) {
+        let _ = writeln!(self.output, "# HELP {name} {help}");
+        let _ = writeln!(self.output, "# TYPE {name} {}", type_.as_str());
+    }
+
+    /// Write a sample of a metric.
+    pub fn sample(&mut self, name: &str, labels: Labels<'_>, value: impl core::fmt::Display) {
+        self.output.push_str(name);
+        self.labels(labels, None);
+        let _ = writeln!(self.output, " {value}");
+    }
+
+    /// Write the samples of a histogram.
+    pub fn histogram(&mut self, name: &str, labels: Labels<'_>, histogram: &Histogram) {
+        let inner = histogram.inner.lock().unwrap().clone();
+
+        let mut cumulative = 0;
+        let bounds = histogram
+            .bounds
+            .iter()
+            .map(ToString::to_string)
+            .chain(core::iter::once("+Inf".to_owned()));
+        for (bound, count) in bounds.zip(&inner.buckets) {
+            cumulative += count;
+            let _ = write!(self.output, "{name}_bucket");
+            self.labels(labels, Some(&bound));
+            let _ = writeln!(self.output, " {cumulative}");
+        }
+
+        self.sample(&format!("{name}_sum"), labels, inner.sum);
+        self.sample(&format!("{name}_count"), labels, cumulative);
+    }
+
+    /// Write the labels, with the optional `le` label.
+    fn labels(&mut self, labels: Labels<'_>, le: Option<&str>) {
+        let le = le.map(|le| ("le", le));
+        let mut labels = labels.iter().copied().chain(le).peekable();
+        if labels.peek().is_none() {
+            return;
+        }
+
+        self.output.push('{');
+        for (index, (name, value)) in labels.enumerate() {
+            if index > 0 {
+                self.output.push(',');
+            }
+            let _ = write!(self.output, "{name}=\"");
+            for char in value.chars() {
+                match char {
+                    '\\' => self.output.push_str("\\\\"),
+                    '"' => self.output.push_str("\\\""),
+                    '\n' => self.output.push_str("\\n"),
+                    char => self.output.push(char),
+                }
+            }
+            self.output.push('"');
+        }
+        self.output.push('}');
+    }
+
+    /// Finish the encoding, returning the output.
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+/// The metrics that can be encoded.
+pub trait Encode {
+    /// Encode the metrics.
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+/// The content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let counters: Family<&'static str, Counter> = Default::default();
+        counters.with("a\"b", Counter::inc);
+        counters.with("c", |counter| counter.inc_by(2));
+
+        let histogram = Histogram::new(&[0.5, 1.0]);
+        histogram.observe(0.1);
+        histogram.observe(0.75);
+        histogram.observe(5.0);
+
+        let mut encoder = Encoder::default();
+        encoder.header("requests_total", "The requests.", Type::Counter);
+        counters.for_each(|key, counter| {
+            encoder.sample("requests_total", &[("key", key)], counter.get())
+        });
+        encoder.header("latency_seconds", "The latency.", Type::Histogram);
+        encoder.histogram("latency_seconds", &[], &histogram);
+
+        assert_eq!(
+            encoder.finish(),
+            r#"# HELP requests_total The requests.
+# TYPE requests_total counter
+requests_total{key="a\"b"} 1
+requests_total{key="c"} 2
+# HELP latency_seconds The latency.
+# TYPE latency_seconds histogram
+latency_seconds_bucket{le="0.5"} 1
+latency_seconds_bucket{le="1"} 2
+latency_seconds_bucket{le="+Inf"} 3
+latency_seconds_sum 5.85
+latency_seconds_count 3
+"#
+        );
+    }
+}
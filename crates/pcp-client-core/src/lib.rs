@@ -7,7 +7,7 @@
 
 use core::future::Future;
 
-/// The size of any PCP packet.
+/// The maximum size of a PCP packet.
 pub const PCP_PACKET_SIZE: usize = 1100;
 
 /// Information about a received packet.
@@ -18,18 +18,26 @@ pub struct RecvInfo {
 
     /// The address of the local socket endpoint at which we have received the packet.
     pub dst: std::net::SocketAddr,
+
+    /// The length of the received packet.
+    pub len: usize,
 }
 
 /// The PCP client transport.
 pub trait Transport {
     /// Send a PCP request to the server.
+    ///
+    /// The request is sent as is, without padding it to any particular size.
     fn send<'a>(
         &'a self,
         to: std::net::SocketAddr,
-        request: &'a [u8; PCP_PACKET_SIZE],
+        request: &'a [u8],
     ) -> impl Future<Output = Result<(), std::io::Error>> + Send + 'a;
 
     /// Receive a PCP response from the server.
+    ///
+    /// Only the packets of valid lengths are to be received, and the part of the buffer
+    /// past the received packet is to be zeroed.
     fn recv<'a>(
         &'a self,
         response: &'a mut [u8; PCP_PACKET_SIZE],
@@ -48,6 +56,11 @@ pub trait Runtime {
     /// `Arc<InnerRuntime>`.
     fn sleep(&self, duration: std::time::Duration) -> Self::SleepFuture;
 
+    /// Get the current time.
+    ///
+    /// Must be consistent with the clock used for [`Self::sleep`].
+    fn now(&self) -> std::time::Instant;
+
     /// Spawn the given future into a background task.
     ///
     /// Does not bound the runtime with the execution of the future, instead requires
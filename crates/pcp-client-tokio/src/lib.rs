@@ -4,30 +4,86 @@ use std::net::SocketAddr;
 
 use pcp_client_core::PCP_PACKET_SIZE;
 
+/// Linux implementation of the destination address detection.
+#[cfg(target_os = "linux")]
+mod pktinfo;
+
 /// The client transport.
 #[derive(Debug)]
 pub struct Transport {
     /// The UDP socket to use.
-    pub socket: tokio::net::UdpSocket,
+    socket: tokio::net::UdpSocket,
 
     /// The local address to report as `dst` for incoming packets.
     ///
-    /// Note this is wrong, and this should be determined dynamically via `IP_PKTINFO`, but
-    /// the correct implementation is left for later.
-    pub local_address: std::net::SocketAddr,
+    /// Only the port is used if the address the packet was sent to is detected via
+    /// `IP_PKTINFO`.
+    local_address: SocketAddr,
+}
+
+impl Transport {
+    /// Create a new [`Transport`] over the socket.
+    ///
+    /// The local address overrides the address the socket is bound to as the address
+    /// to report when the address the packet was sent to can not be detected.
+    pub fn new(
+        socket: tokio::net::UdpSocket,
+        local_ip_address: Option<std::net::IpAddr>,
+    ) -> Result<Self, std::io::Error> {
+        #[cfg(target_os = "linux")]
+        pktinfo::enable(&socket)?;
+
+        let mut local_address = socket.local_addr()?;
+        if let Some(local_ip_address) = local_ip_address {
+            local_address.set_ip(local_ip_address);
+        }
+
+        Ok(Self {
+            socket,
+            local_address,
+        })
+    }
+
+    /// The local address to report when the address the packet was sent to is unknown.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Receive a packet, returning its length, source address and, if detected,
+    /// the destination address.
+    #[cfg(target_os = "linux")]
+    async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<std::net::IpAddr>), std::io::Error> {
+        use std::os::fd::AsRawFd as _;
+
+        self.socket
+            .async_io(tokio::io::Interest::READABLE, || {
+                pktinfo::recv(self.socket.as_raw_fd(), buf)
+            })
+            .await
+    }
+
+    /// Receive a packet, returning its length, source address and, if detected,
+    /// the destination address.
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<std::net::IpAddr>), std::io::Error> {
+        let (len, from) = self.socket.recv_from(buf).await?;
+        Ok((len, from, None))
+    }
 }
 
 impl pcp_client_core::Transport for Transport {
-    async fn send<'a>(
-        &'a self,
-        to: SocketAddr,
-        request: &'a [u8; PCP_PACKET_SIZE],
-    ) -> Result<(), std::io::Error> {
+    async fn send<'a>(&'a self, to: SocketAddr, request: &'a [u8]) -> Result<(), std::io::Error> {
         tracing::debug!(message = "sending packet", ?to, ?request);
 
         let len = self.socket.send_to(request, to).await?;
 
-        if len != PCP_PACKET_SIZE {
+        if len != request.len() {
             return Err(std::io::Error::other("unable to write full packet"));
         }
 
@@ -38,18 +94,29 @@ impl pcp_client_core::Transport for Transport {
         &'a self,
         response: &'a mut [u8; PCP_PACKET_SIZE],
     ) -> Result<pcp_client_core::RecvInfo, std::io::Error> {
-        let (len, from) = self.socket.recv_from(response).await?;
+        loop {
+            let (len, from, to) = self.recv_from(response).await?;
 
-        tracing::debug!(message = "received packet", ?from, ?response);
+            tracing::debug!(message = "received packet", ?from, ?to, packet = ?&response[..len]);
 
-        if len != PCP_PACKET_SIZE {
-            return Err(std::io::Error::other("invalid packet received"));
-        }
+            if !pcp_packet::is_valid_len(len) {
+                tracing::warn!(message = "invalid packet length, ignoring", ?from, len);
+                continue;
+            }
 
-        Ok(pcp_client_core::RecvInfo {
-            src: from,
-            dst: self.local_address, // FIXME: use `IP_PKTINFO` to properly detect this
-        })
+            response[len..].fill(0);
+
+            let mut dst = self.local_address;
+            if let Some(to) = to {
+                dst.set_ip(to);
+            }
+
+            return Ok(pcp_client_core::RecvInfo {
+                src: from,
+                dst,
+                len,
+            });
+        }
     }
 }
 
@@ -64,6 +131,10 @@ impl pcp_client_core::Runtime for Runtime {
         tokio::time::sleep(duration)
     }
 
+    fn now(&self) -> std::time::Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
     fn spawn_background(&self, fut: impl std::future::Future<Output = ()> + Send + 'static) {
         tokio::spawn(fut);
     }
@@ -0,0 +1,210 @@
+//! Detection of the local address the packets are received at via `IP_PKTINFO`
+//! and `IPV6_RECVPKTINFO`.
+//!
+//! Required when the socket is bound to the unspecified address, as the local address
+//! of the socket does not tell which address the packet was sent to.
+
+#![allow(unsafe_code)]
+
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::fd::{AsRawFd as _, RawFd},
+};
+
+/// Enable the packet info reporting at the socket.
+pub fn enable(socket: &tokio::net::UdpSocket) -> io::Result<()> {
+    let fd = socket.as_raw_fd();
+    match socket.local_addr()? {
+        SocketAddr::V4(_) => set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO),
+        SocketAddr::V6(_) => {
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)?;
+            // The IPv4 packets arriving at the dual-stack socket carry the IPv4 packet info.
+            // This is not supported at the IPv6-only sockets, which is fine.
+            let _ = set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO);
+            Ok(())
+        }
+    }
+}
+
+/// Enable the boolean socket option.
+fn set_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+    let value: libc::c_int = 1;
+    // SAFETY: the value pointer and length describe a valid `c_int`.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive a packet from the non-blocking socket.
+///
+/// Returns the length of the packet, the address it was sent from and the address it was
+/// sent to, if reported.
+///
+/// The packets that do not fit into the buffer are dropped.
+pub fn recv(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    loop {
+        if let Some(received) = recv_once(fd, buf)? {
+            return Ok(received);
+        }
+    }
+}
+
+/// Receive a single packet from the non-blocking socket.
+///
+/// Returns [`None`] if the packet was truncated.
+fn recv_once(fd: RawFd, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr, Option<IpAddr>)>> {
+    // SAFETY: all-zeroes is a valid `sockaddr_storage`.
+    let mut src: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // Large enough for either of the packet info control messages, and aligned for `cmsghdr`.
+    let mut control = [0u64; 16];
+
+    // SAFETY: all-zeroes is a valid `msghdr`.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = (&mut src as *mut libc::sockaddr_storage).cast();
+    msg.msg_namelen = mem::size_of_val(&src) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: the message header points to the buffers that outlive the call.
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The rest of the datagram is discarded by the kernel already.
+    if msg.msg_flags & libc::MSG_TRUNC != 0 {
+        tracing::warn!(
+            message = "dropped the packet that does not fit into the buffer",
+            len
+        );
+        return Ok(None);
+    }
+
+    let src = socket_address(&src)?;
+
+    let mut dst = None;
+    // SAFETY: the message header was filled by the `recvmsg`.
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        // SAFETY: the control message pointer is non-null and points into the control buffer.
+        let header = unsafe { &*cmsg };
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                // SAFETY: the control message of this type carries the `in_pktinfo`.
+                let info: libc::in_pktinfo =
+                    unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast()) };
+                dst = Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)).into());
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                // SAFETY: the control message of this type carries the `in6_pktinfo`.
+                let info: libc::in6_pktinfo =
+                    unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast()) };
+                dst = Some(Ipv6Addr::from(info.ipi6_addr.s6_addr).into());
+            }
+            _ => {}
+        }
+        // SAFETY: the control message pointer was obtained from the same message header.
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    Ok(Some((len as usize, src, dst)))
+}
+
+/// Convert the raw socket address.
+fn socket_address(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match libc::c_int::from(storage.ss_family) {
+        libc::AF_INET => {
+            // SAFETY: the storage holds the `sockaddr_in` for this family.
+            let addr: libc::sockaddr_in =
+                unsafe { std::ptr::read((storage as *const libc::sockaddr_storage).cast()) };
+            Ok(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )
+            .into())
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the storage holds the `sockaddr_in6` for this family.
+            let addr: libc::sockaddr_in6 =
+                unsafe { std::ptr::read((storage as *const libc::sockaddr_storage).cast()) };
+            Ok(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )
+            .into())
+        }
+        family => Err(io::Error::other(format!(
+            "unexpected address family: {family}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn detects_destination() {
+        let server = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        enable(&server).unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(b"hello", (Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        let mut buf = [0; 16];
+        let (len, src, dst) = server
+            .async_io(tokio::io::Interest::READABLE, || {
+                recv(server.as_raw_fd(), &mut buf)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(src, client.local_addr().unwrap());
+        assert_eq!(dst, Some(Ipv4Addr::LOCALHOST.into()));
+    }
+
+    #[tokio::test]
+    async fn drops_truncated() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        enable(&server).unwrap();
+        let server_address = server.local_addr().unwrap();
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&[0; 32], server_address).await.unwrap();
+        client.send_to(b"hello", server_address).await.unwrap();
+
+        let mut buf = [0; 16];
+        let (len, _, _) = server
+            .async_io(tokio::io::Interest::READABLE, || {
+                recv(server.as_raw_fd(), &mut buf)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(&buf[..len], b"hello");
+    }
+}
@@ -0,0 +1,93 @@
+//! Accounting of the dropped incoming packets.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The reason an incoming packet was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// The packet did not come from the PCP server the requests are sent to.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/rfc6887#section-8.3>.
+    UnexpectedSource,
+
+    /// The packet is not a `MAP`, `PEER` or `ANNOUNCE` response.
+    UnexpectedOpcode,
+}
+
+impl Reason {
+    /// All of the reasons.
+    pub const ALL: [Self; 2] = [Self::UnexpectedSource, Self::UnexpectedOpcode];
+
+    /// The name of the reason, in `snake_case`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UnexpectedSource => "unexpected_source",
+            Self::UnexpectedOpcode => "unexpected_opcode",
+        }
+    }
+}
+
+/// The counters of the dropped packets, per reason.
+#[derive(Debug, Default)]
+pub struct Counters {
+    unexpected_source: AtomicU64,
+    unexpected_opcode: AtomicU64,
+}
+
+impl Counters {
+    fn counter(&self, reason: Reason) -> &AtomicU64 {
+        match reason {
+            Reason::UnexpectedSource => &self.unexpected_source,
+            Reason::UnexpectedOpcode => &self.unexpected_opcode,
+        }
+    }
+
+    /// Count a packet dropped for the reason.
+    pub fn record(&self, reason: Reason) {
+        self.counter(reason).fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the number of the packets dropped for the reason.
+    pub fn get(&self, reason: Reason) -> u64 {
+        self.counter(reason).load(Ordering::Relaxed)
+    }
+}
+
+/// Check if the packet came from the PCP server.
+///
+/// The unsolicited `ANNOUNCE`s are multicast from the same address and port the server
+/// responds from, so there is no need to treat them separately.
+///
+/// See <https://datatracker.ietf.org/doc/html/rfc6887#section-14.1.3>.
+pub fn is_from_server(src: std::net::SocketAddr, server_address: std::net::SocketAddr) -> bool {
+    src.port() == server_address.port()
+        && pcp_ip_conv::unify(src.ip()) == pcp_ip_conv::unify(server_address.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source() {
+        let server = "192.0.2.1:5351".parse().unwrap();
+
+        assert!(is_from_server(server, server));
+        assert!(is_from_server(
+            "[::ffff:192.0.2.1]:5351".parse().unwrap(),
+            server
+        ));
+        assert!(!is_from_server("192.0.2.1:5350".parse().unwrap(), server));
+        assert!(!is_from_server("192.0.2.2:5351".parse().unwrap(), server));
+    }
+
+    #[test]
+    fn counters() {
+        let counters = Counters::default();
+        counters.record(Reason::UnexpectedSource);
+        counters.record(Reason::UnexpectedSource);
+
+        assert_eq!(counters.get(Reason::UnexpectedSource), 2);
+        assert_eq!(counters.get(Reason::UnexpectedOpcode), 0);
+    }
+}
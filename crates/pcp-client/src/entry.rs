@@ -0,0 +1,336 @@
+//! The per-mapping bookkeeping of the client.
+
+use std::{
+    collections::{hash_map, HashMap},
+    hash::Hash,
+    time::Instant,
+};
+
+use crate::{metrics::Metrics, request, retransmit};
+
+/// A mapping tracked by the client.
+#[derive(Debug)]
+pub struct Entry<Mapping, Incoming>
+where
+    Mapping: pcp_lifecycle::Mapping + pcp_lifecycle::cleanup::Into<Mapping = Mapping>,
+    Incoming:
+        pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<Mapping>,
+{
+    /// The lifecycle state of the mapping.
+    pub state: pcp_lifecycle::State<Mapping, Mapping, Incoming>,
+
+    /// The retransmission schedule of the request that is still awaiting a response.
+    pub retransmission: Option<retransmit::Schedule>,
+}
+
+/// The entries of a kind, by ID.
+pub type Entries<Id, Mapping, Incoming> = HashMap<Id, Entry<Mapping, Incoming>>;
+
+/// The lifecycle phase of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase {
+    /// The desired mapping has not been granted yet.
+    Pending,
+
+    /// The desired mapping is granted.
+    Active,
+
+    /// The server has responded with an error.
+    Error,
+
+    /// The mapping is no longer desired and is being deleted.
+    Releasing,
+}
+
+impl Phase {
+    /// All of the phases.
+    pub const ALL: [Self; 4] = [Self::Pending, Self::Active, Self::Error, Self::Releasing];
+
+    /// The name of the phase, in `snake_case`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Active => "active",
+            Self::Error => "error",
+            Self::Releasing => "releasing",
+        }
+    }
+}
+
+impl<M, I> Entry<M, I>
+where
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    /// Create a new entry with the given desired mapping.
+    pub fn new(mapping: M) -> Self {
+        Self {
+            state: pcp_lifecycle::State::new(mapping),
+            retransmission: None,
+        }
+    }
+
+    /// The lifecycle phase of the entry.
+    pub fn phase(&self) -> Phase {
+        if self.state.desired().is_none() {
+            Phase::Releasing
+        } else if self.state.last_error().is_some() {
+            Phase::Error
+        } else if self.state.effective().is_some() {
+            Phase::Active
+        } else {
+            Phase::Pending
+        }
+    }
+
+    /// Check if the entry has anything to send to the server at the given time.
+    pub fn has_pending_actions(&self, now: Instant) -> bool {
+        let pcp_lifecycle::PendingActions { renew, cleanup } = self.state.pending_actions(now);
+        renew.is_some() || !cleanup.is_empty()
+    }
+
+    /// The earliest time at which the entry has something to send to the server.
+    ///
+    /// While a request is awaiting a response this is its retransmission time, otherwise
+    /// it is the renewal time or the end of the error lifetime.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match self.retransmission {
+            Some(schedule) => Some(schedule.deadline()),
+            None => self.state.next_action_at(),
+        }
+    }
+
+    /// Send the pending cleanups and the renewal.
+    ///
+    /// Returns `true` if at least one request was sent.
+    async fn send<Transport: pcp_client_core::Transport>(
+        &self,
+        transport: &Transport,
+        server_address: std::net::SocketAddr,
+        packet: &mut pcp_packet::Buffer,
+        metrics: &Metrics,
+        now: Instant,
+    ) -> bool {
+        let pcp_lifecycle::PendingActions { renew, cleanup } = self.state.pending_actions(now);
+
+        let cleanup = cleanup.iter().map(|op| (op, None));
+        let renew = renew.map(|op| (op, self.state.suggestion()));
+
+        let mut sent = false;
+        for (op, suggestion) in cleanup.chain(renew) {
+            let request = op.encode(packet, suggestion);
+
+            match transport.send(server_address, request).await {
+                Ok(()) => {
+                    metrics.requests_sent.with(M::OPCODE, metrics::Counter::inc);
+                    sent = true
+                }
+                Err(error) => tracing::error!(message = "error while sending PCP packet", ?error),
+            }
+        }
+        sent
+    }
+}
+
+/// Send the pending cleanups and renewals for all of the given entries.
+///
+/// Drops the entries that have nothing left to manage. The requests that are awaiting
+/// a response are only sent again when they are due for a retransmission, and the renewals
+/// only once they are due.
+pub async fn send_pending<Transport, Id, M, I>(
+    transport: &Transport,
+    server_address: std::net::SocketAddr,
+    entries: &mut Entries<Id, M, I>,
+    packet: &mut pcp_packet::Buffer,
+    retransmit: &retransmit::Params,
+    metrics: &Metrics,
+    now: Instant,
+) where
+    Transport: pcp_client_core::Transport,
+    Id: core::fmt::Debug,
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    entries.retain(|_, entry| !entry.state.is_idle());
+
+    for (id, entry) in entries.iter_mut() {
+        if !entry.has_pending_actions(now) {
+            entry.retransmission = None;
+            continue;
+        }
+
+        entry.retransmission = match entry.retransmission {
+            None => Some(retransmit::Schedule::start(
+                retransmit,
+                now,
+                retransmit::random_factor(),
+            )),
+            Some(schedule) if schedule.deadline() > now => continue,
+            Some(schedule) => {
+                let schedule = schedule.advance(retransmit, now, retransmit::random_factor());
+                if schedule.is_none() {
+                    // Start over once the maximum retransmission timeout passes.
+                    tracing::warn!(message = "giving up retransmitting PCP request", ?id);
+                    entry.state.postpone(now + retransmit.max_timeout);
+                    entry.retransmission = None;
+                    continue;
+                }
+                tracing::debug!(message = "retransmitting PCP request", ?id);
+                metrics
+                    .retransmissions
+                    .with(M::OPCODE, metrics::Counter::inc);
+                schedule
+            }
+        };
+
+        entry
+            .send(transport, server_address, packet, metrics, now)
+            .await;
+    }
+}
+
+/// The earliest time at which any of the entries has something to send to the server.
+pub fn next_deadline<Id, M, I>(entries: &Entries<Id, M, I>) -> Option<Instant>
+where
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    entries.values().filter_map(Entry::next_deadline).min()
+}
+
+/// Update the gauges of the entries by lifecycle phase.
+pub fn record_phases<Id, M, I>(entries: &Entries<Id, M, I>, metrics: &Metrics)
+where
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    let mut counts = [0; Phase::ALL.len()];
+    for entry in entries.values() {
+        counts[entry.phase() as usize] += 1;
+    }
+
+    for (phase, count) in Phase::ALL.into_iter().zip(counts) {
+        metrics
+            .mappings
+            .with((M::OPCODE, phase), |gauge| gauge.set(count));
+    }
+}
+
+/// Make the renewals of all of the entries due right away.
+pub fn expedite_renewals<Id, M, I>(entries: &mut Entries<Id, M, I>)
+where
+    M: pcp_lifecycle::Mapping + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    for entry in entries.values_mut() {
+        entry.state.expedite_renewal();
+        entry.retransmission = None;
+    }
+}
+
+/// Apply the server notification to the entry of the corresponding mapping.
+///
+/// Returns `false` if the mapping is not in the lifecycle.
+pub fn apply_server_notification<Id, M, I>(
+    entries: &mut Entries<Id, M, I>,
+    id: Id,
+    incoming: I,
+    metrics: &Metrics,
+    now: Instant,
+) -> bool
+where
+    Id: Eq + Hash + core::fmt::Debug,
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe
+        + pcp_lifecycle::Response
+        + pcp_lifecycle::Incoming<M>
+        + core::fmt::Debug,
+{
+    metrics.record_response(M::OPCODE, incoming.result_code());
+
+    let Some(entry) = entries.get_mut(&id) else {
+        tracing::warn!(
+            message = "received a server notification a mapping that is not in the lifecycle",
+            ?incoming,
+            ?id,
+        );
+        return false;
+    };
+
+    match pcp_lifecycle::result::classify(incoming.result_code()) {
+        pcp_lifecycle::result::Class::Success => {
+            if let Some(schedule) = entry.retransmission {
+                let latency = now.saturating_duration_since(schedule.started_at());
+                metrics.renewal_latency.observe(latency.as_secs_f64());
+            }
+        }
+        pcp_lifecycle::result::Class::Error(kind) => {
+            tracing::warn!(
+                message = "PCP server responded with an error",
+                ?id,
+                result_code = incoming.result_code(),
+                ?kind,
+                lifetime = ?incoming.lifetime(),
+            );
+        }
+    }
+
+    entry
+        .state
+        .handle_server_notification(incoming, now, rand::random());
+    entry.retransmission = None;
+    true
+}
+
+/// Insert or update the desired mapping.
+pub fn upsert_desired<Id, M, I>(entries: &mut Entries<Id, M, I>, id: Id, mapping: M)
+where
+    Id: Eq + Hash,
+    M: request::Encode<Response = I>
+        + pcp_lifecycle::Mapping
+        + pcp_lifecycle::cleanup::Into<Mapping = M>
+        + PartialEq,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    match entries.entry(id) {
+        hash_map::Entry::Occupied(mut entry) => {
+            let entry = entry.get_mut();
+            match entry.state.update_desired(mapping) {
+                pcp_lifecycle::UpdateDesiredOutcome::Unchanged => {}
+                // The request has changed, so the outstanding one is not worth retransmitting.
+                _ => entry.retransmission = None,
+            }
+        }
+        hash_map::Entry::Vacant(entry) => {
+            entry.insert(Entry::new(mapping));
+        }
+    }
+}
+
+/// Remove the desired mapping, scheduling the cleanup.
+///
+/// Returns `false` if the mapping is not in the lifecycle.
+pub fn remove_desired<Id, M, I>(entries: &mut Entries<Id, M, I>, id: &Id) -> bool
+where
+    Id: Eq + Hash,
+    M: pcp_lifecycle::Mapping + pcp_lifecycle::cleanup::Into<Mapping = M>,
+    I: pcp_lifecycle::cleanup::Maybe + pcp_lifecycle::Response + pcp_lifecycle::Incoming<M>,
+{
+    let Some(entry) = entries.get_mut(id) else {
+        return false;
+    };
+
+    let _ = entry.state.remove_desired();
+    entry.retransmission = None;
+    true
+}
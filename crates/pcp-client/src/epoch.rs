@@ -0,0 +1,97 @@
+//! Server epoch tracking.
+//!
+//! Used to detect the PCP server losing its mappings state.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-8.5>.
+
+use pcp_primitives::EpochTime;
+
+/// The epoch of a single PCP server as we have last observed it.
+#[derive(Debug, Clone, Copy)]
+pub struct Tracker {
+    /// The server epoch time from the last response.
+    prev_server_time: EpochTime,
+
+    /// The time at which the last response was received.
+    prev_client_time: std::time::Instant,
+}
+
+impl Tracker {
+    /// Start tracking from the first observed response.
+    pub fn new(server_time: EpochTime, client_time: std::time::Instant) -> Self {
+        Self {
+            prev_server_time: server_time,
+            prev_client_time: client_time,
+        }
+    }
+
+    /// Observe the epoch time of a new response.
+    ///
+    /// Returns `true` if the server has lost its state since the previous observation.
+    pub fn observe(
+        &mut self,
+        curr_server_time: EpochTime,
+        curr_client_time: std::time::Instant,
+    ) -> bool {
+        let prev = core::mem::replace(self, Self::new(curr_server_time, curr_client_time));
+
+        // The server epoch went backwards.
+        if u64::from(curr_server_time) + 1 < u64::from(prev.prev_server_time) {
+            return true;
+        }
+
+        let client_delta = curr_client_time
+            .saturating_duration_since(prev.prev_client_time)
+            .as_secs();
+        let server_delta = u64::from(curr_server_time.saturating_sub(prev.prev_server_time));
+
+        // The server and the client clocks diverged more than the slack allows.
+        client_delta + 2 < server_delta - server_delta / 16
+            || server_delta + 2 < client_delta - client_delta / 16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn steady() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1000, start);
+
+        assert!(!tracker.observe(1030, start + Duration::from_secs(30)));
+        assert!(!tracker.observe(1061, start + Duration::from_secs(60)));
+        assert!(!tracker.observe(1060, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn went_backwards() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1000, start);
+
+        assert!(tracker.observe(5, start + Duration::from_secs(30)));
+
+        // Tracking continues from the new epoch.
+        assert!(!tracker.observe(35, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn restarted_between_responses() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1000, start);
+
+        // The server has been up for much less time than has passed since the last response.
+        assert!(tracker.observe(1010, start + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn ran_ahead() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1000, start);
+
+        assert!(tracker.observe(2000, start + Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,22 @@
+//! The health of the client.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The health of the client, shared with the observers.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Whether the server has responded at least once.
+    answered: AtomicBool,
+}
+
+impl Health {
+    /// Record that the server has responded.
+    pub fn record_answer(&self) {
+        self.answered.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if the server has responded at least once.
+    pub fn has_answered(&self) -> bool {
+        self.answered.load(Ordering::Relaxed)
+    }
+}
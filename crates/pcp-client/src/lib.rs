@@ -2,11 +2,21 @@
 
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 
+pub mod dropped;
+pub mod entry;
+pub mod epoch;
+pub mod health;
 pub mod mapping;
+pub mod metrics;
+pub mod peer;
+pub mod probe;
+mod request;
+pub mod retransmit;
+
 pub use mapping::Mapping;
 
 use std::{
-    collections::{hash_map, HashMap, HashSet},
+    collections::{hash_map, HashMap},
     future::Future,
 };
 
@@ -15,9 +25,37 @@ pub struct Client<Runtime, Transport> {
     pub runtime: Runtime,
     pub transport: Transport,
     pub server_address: std::net::SocketAddr,
-    pub mappings: HashMap<mapping::Id, pcp_lifecycle::State<Mapping, Mapping, mapping::Incoming>>,
-    pub keepalive_interval: std::time::Duration,
-    pub notifications_tx: tokio::sync::mpsc::Sender<mapping::Incoming>,
+    pub mappings: entry::Entries<mapping::Id, Mapping, mapping::Incoming>,
+    pub peers: entry::Entries<peer::Id, peer::Mapping, peer::Incoming>,
+    pub epochs: HashMap<std::net::SocketAddr, epoch::Tracker>,
+    pub retransmit: retransmit::Params,
+    pub notifications_tx: tokio::sync::mpsc::Sender<Notification>,
+    pub metrics: std::sync::Arc<metrics::Metrics>,
+    pub health: std::sync::Arc<health::Health>,
+    pub probe: Option<probe::Probe>,
+}
+
+/// The notifications the client emits about the mappings.
+#[derive(Debug, Clone)]
+pub enum Notification {
+    /// A response for a `MAP` mapping was received.
+    Incoming(mapping::Incoming),
+
+    /// The server has lost its state and all of the mappings are being re-sent.
+    ServerStateLost(ServerStateLost),
+}
+
+/// The details of the server state loss.
+#[derive(Debug, Clone)]
+pub struct ServerStateLost {
+    /// The address of the server that has lost the state.
+    pub server_address: std::net::SocketAddr,
+
+    /// The epoch time the server reported.
+    pub epoch_time: pcp_primitives::EpochTime,
+
+    /// The `MAP` mappings affected by the state loss.
+    pub mapping_ids: Vec<mapping::Id>,
 }
 
 #[derive(Debug)]
@@ -29,6 +67,19 @@ pub enum Command {
         mapping::Id,
         tokio::sync::oneshot::Sender<Option<mapping::Incoming>>,
     ),
+    Peer(PeerCommand),
+}
+
+/// The commands for managing the `PEER` mappings.
+#[derive(Debug)]
+pub enum PeerCommand {
+    UpsertDesired(peer::Mapping),
+    RemoveDesired(peer::Id),
+    HasState(peer::Id, tokio::sync::oneshot::Sender<bool>),
+    GetEffective(
+        peer::Id,
+        tokio::sync::oneshot::Sender<Option<peer::Incoming>>,
+    ),
 }
 
 impl<Runtime, Transport> Client<Runtime, Transport>
@@ -37,132 +88,211 @@ where
     Transport: pcp_client_core::Transport,
 {
     async fn reconcile_once(&mut self) {
-        let mut cleanups = Vec::new();
-        let mut renews = Vec::new();
-        let mut ids_to_remove = HashSet::new();
-
-        for (&id, state) in &self.mappings {
-            let pcp_lifecycle::PendingActions { renew, cleanup } = state.pending_actions();
-            if !cleanup.is_empty() {
-                cleanups.push((id, cleanup));
-            }
-            if let Some(renew) = renew {
-                renews.push((id, renew));
-            }
+        let mut packet = [0; pcp_packet::LEN];
+        let now = self.runtime.now();
 
-            if renew.is_none() && cleanup.is_empty() {
-                ids_to_remove.insert(id);
+        if let Some(probe) = &mut self.probe {
+            if probe.advance(&self.retransmit, now) {
+                let request = probe.encode(&mut packet);
+                if let Err(error) = self.transport.send(self.server_address, request).await {
+                    tracing::error!(message = "error while sending PCP packet", ?error);
+                }
             }
         }
 
-        let ops = cleanups
-            .into_iter()
-            .flat_map(|(id, items)| items.iter().map(move |val| (id, val)));
-        let ops = ops.chain(renews.into_iter());
+        entry::send_pending(
+            &self.transport,
+            self.server_address,
+            &mut self.mappings,
+            &mut packet,
+            &self.retransmit,
+            &self.metrics,
+            now,
+        )
+        .await;
+
+        entry::send_pending(
+            &self.transport,
+            self.server_address,
+            &mut self.peers,
+            &mut packet,
+            &self.retransmit,
+            &self.metrics,
+            now,
+        )
+        .await;
+    }
 
-        let mut packet = [0; pcp_packet::LEN];
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        let mappings = entry::next_deadline(&self.mappings);
+        let peers = entry::next_deadline(&self.peers);
+        let probe = self.probe.as_ref().and_then(probe::Probe::deadline);
+        mappings.into_iter().chain(peers).chain(probe).min()
+    }
 
-        for (id, op) in ops {
-            let Mapping {
-                id:
-                    mapping::Id {
-                        protocol,
-                        internal_ip,
-                        internal_port,
-                        nonce,
-                    },
-                params:
-                    mapping::Params {
-                        lifetime,
-                        external_port,
-                        exteranl_ip,
-                        third_party: _,
-                        prefer_failure,
-                        filters: _,
-                    },
-            } = op;
+    /// Sleep until the given time, or forever if there is none.
+    async fn sleep_until(&self, deadline: Option<std::time::Instant>) {
+        let Some(deadline) = deadline else {
+            return std::future::pending().await;
+        };
+        let duration = deadline.saturating_duration_since(self.runtime.now());
+        self.runtime.sleep(duration).await
+    }
 
-            let enc = pcp_codec::encode::State::new(&mut packet).request().map(
-                pcp_codec::data::request::Header {
-                    requested_lifetime: *lifetime,
-                    client_ip_address: *internal_ip,
-                },
-                pcp_codec::data::request::Map {
-                    mapping_nonce: *nonce,
-                    protocol: *protocol,
-                    internal_port: *internal_port,
-                    suggested_external_port: *external_port,
-                    suggested_external_ip_address: *exteranl_ip,
-                },
+    async fn apply_incoming(
+        &mut self,
+        packet: &pcp_packet::Buffer,
+        recv_info: &pcp_client_core::RecvInfo,
+    ) {
+        let received_on = pcp_ip_conv::unify(recv_info.dst.ip());
+
+        // Spoofed responses must not affect the mappings.
+        if !dropped::is_from_server(recv_info.src, self.server_address) {
+            self.metrics
+                .dropped
+                .record(dropped::Reason::UnexpectedSource);
+            tracing::warn!(
+                message = "PCP packet not from the PCP server, dropping",
+                src = %recv_info.src,
+                server_address = %self.server_address,
             );
+            return;
+        }
 
-            let request = if let Some(prefer_failure) = prefer_failure {
-                let mut option_code = pcp_consts::option::PREFER_FAILURE;
-                if prefer_failure.is_optional {
-                    option_code |= 0b1000_0000;
-                }
-                enc.add_option(option_code, &[]).finish()
-            } else {
-                enc.finish()
-            };
+        let now = self.runtime.now();
+        let decoder = pcp_codec::decode::State::with_len(packet, recv_info.len);
 
-            if let Err(error) = self.transport.send(self.server_address, request).await {
-                tracing::error!(message = "error while sending PCP packet", ?error);
-                ids_to_remove.remove(&id);
+        for option in decoder.options() {
+            match option {
+                Ok(option) => tracing::debug!(message = "PCP response option", ?option),
+                Err(error) => {
+                    tracing::warn!(message = "invalid PCP response options", ?error, %received_on)
+                }
             }
         }
 
-        for id in ids_to_remove {
-            self.mappings.remove(&id);
-        }
-    }
+        let (header, applied, announced) = if let Some((header, opcode)) =
+            decoder.map_response_data()
+        {
+            let incoming = mapping::Incoming {
+                received_on,
+                packet_header: header,
+                packet_opcode: opcode,
+            };
 
-    async fn apply_incoming(
-        &mut self,
-        packet: &pcp_packet::Buffer,
-        received_on: pcp_primitives::Address,
-    ) {
-        let incoming = pcp_codec::decode::State::new(packet);
+            self.runtime
+                .spawn_background(self.notify(Notification::Incoming(incoming)));
 
-        let Some((header, opcode)) = incoming.map_response_data() else {
+            let applied = entry::apply_server_notification(
+                &mut self.mappings,
+                incoming.id(),
+                incoming,
+                &self.metrics,
+                now,
+            );
+            (header, applied, false)
+        } else if let Some((header, opcode)) = decoder.peer_response_data() {
+            let incoming = peer::Incoming {
+                received_on,
+                packet_header: header,
+                packet_opcode: opcode,
+            };
+
+            let applied = entry::apply_server_notification(
+                &mut self.peers,
+                incoming.id(),
+                incoming,
+                &self.metrics,
+                now,
+            );
+            (header, applied, false)
+        } else if let Some(header) = decoder.announce_response_data() {
+            let probe_outstanding = self
+                .probe
+                .as_ref()
+                .is_some_and(|probe| probe.deadline().is_some());
+            if probe_outstanding {
+                // The response to our probe can not be told from the unsolicited
+                // `ANNOUNCE`, but the epoch tracking catches the server restart anyway.
+                tracing::info!(message = "PCP server answered the probe", ?header, %received_on);
+                self.probe = None;
+                (header, false, false)
+            } else {
+                // The server announces it has restarted and might have lost the mappings state,
+                // so we have to re-send every desired mapping right away.
+                //
+                // See <https://datatracker.ietf.org/doc/html/rfc6887#section-14.1.3>.
+                tracing::info!(
+                    message = "ANNOUNCE received, re-sending all desired mappings",
+                    ?header,
+                    %received_on,
+                );
+                (header, false, true)
+            }
+        } else {
+            self.metrics
+                .dropped
+                .record(dropped::Reason::UnexpectedOpcode);
             tracing::warn!(
-                message = "unexpected non-MAP-response packet received",
+                message = "unexpected non-ANNOUNCE/MAP/PEER-response packet received",
                 ?packet,
                 %received_on,
             );
             return;
         };
 
-        let incoming = mapping::Incoming {
-            received_on,
-            packet_header: header,
-            packet_opcode: opcode,
-        };
+        self.health.record_answer();
+
+        let state_lost = self.observe_epoch(recv_info.src, header.epoch_time);
+
+        if announced || state_lost {
+            entry::expedite_renewals(&mut self.mappings);
+            entry::expedite_renewals(&mut self.peers);
+        } else if !applied {
+            return;
+        }
 
-        self.runtime
-            .spawn_background(self.notify_about_incoming(incoming));
+        self.reconcile_once().await
+    }
+
+    /// Track the epoch of the server that sent a response.
+    ///
+    /// Returns `true` if the server has lost its state, in which case all of the mappings
+    /// have to be re-sent.
+    fn observe_epoch(
+        &mut self,
+        server_address: std::net::SocketAddr,
+        epoch_time: pcp_primitives::EpochTime,
+    ) -> bool {
+        let now = self.runtime.now();
 
-        let id = incoming.id();
+        let state_lost = match self.epochs.entry(server_address) {
+            hash_map::Entry::Occupied(entry) => entry.into_mut().observe(epoch_time, now),
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(epoch::Tracker::new(epoch_time, now));
+                false
+            }
+        };
 
-        let Some(state) = self.mappings.get_mut(&id) else {
+        if state_lost {
             tracing::warn!(
-                message = "received a server notification a mapping that is not in the lifecycle",
-                ?incoming,
-                ?packet,
-                ?id,
+                message = "PCP server has lost its state, re-sending all desired mappings",
+                %server_address,
+                %epoch_time,
             );
-            return;
-        };
 
-        state.handle_server_notification(incoming);
+            let notification = Notification::ServerStateLost(ServerStateLost {
+                server_address,
+                epoch_time,
+                mapping_ids: self.mappings.keys().copied().collect(),
+            });
+            self.runtime.spawn_background(self.notify(notification));
+        }
 
-        self.reconcile_once().await
+        state_lost
     }
 
-    fn notify_about_incoming(
-        &self,
-        value: mapping::Incoming,
-    ) -> impl Future<Output = ()> + Send + 'static {
+    fn notify(&self, value: Notification) -> impl Future<Output = ()> + Send + 'static {
         let tx = self.notifications_tx.clone();
         async move {
             let _ = tx.send(value).await;
@@ -170,24 +300,26 @@ where
     }
 
     async fn upsert_desired(&mut self, mapping: Mapping) {
-        match self.mappings.entry(mapping.id) {
-            hash_map::Entry::Occupied(mut entry) => {
-                let _ = entry.get_mut().update_desired(mapping);
-            }
-            hash_map::Entry::Vacant(entry) => {
-                entry.insert(pcp_lifecycle::State::new(mapping));
-            }
-        }
-
+        entry::upsert_desired(&mut self.mappings, mapping.id, mapping);
         self.reconcile_once().await;
     }
 
     async fn remove_desired(&mut self, id: mapping::Id) {
-        let Some(state) = self.mappings.get_mut(&id) else {
+        if !entry::remove_desired(&mut self.mappings, &id) {
             return;
-        };
+        }
+        self.reconcile_once().await;
+    }
+
+    async fn upsert_desired_peer(&mut self, mapping: peer::Mapping) {
+        entry::upsert_desired(&mut self.peers, mapping.id, mapping);
+        self.reconcile_once().await;
+    }
 
-        let _ = state.remove_desired();
+    async fn remove_desired_peer(&mut self, id: peer::Id) {
+        if !entry::remove_desired(&mut self.peers, &id) {
+            return;
+        }
         self.reconcile_once().await;
     }
 
@@ -196,8 +328,13 @@ where
     }
 
     fn effective_mapping(&self, id: mapping::Id) -> Option<&mapping::Incoming> {
-        let state = self.mappings.get(&id)?;
-        state.effective()
+        let entry = self.mappings.get(&id)?;
+        entry.state.effective()
+    }
+
+    fn effective_peer(&self, id: peer::Id) -> Option<&peer::Incoming> {
+        let entry = self.peers.get(&id)?;
+        entry.state.effective()
     }
 
     async fn handle_command(&mut self, command: Command) {
@@ -210,22 +347,49 @@ where
             Command::GetEffective(id, tx) => {
                 let _ = tx.send(self.effective_mapping(id).cloned());
             }
+            Command::Peer(command) => self.handle_peer_command(command).await,
         }
     }
 
-    pub async fn lifecycle_loop(&mut self, mut rx: tokio::sync::mpsc::Receiver<Command>) {
-        let mut next_keepalive = Box::pin(self.runtime.sleep(self.keepalive_interval));
+    async fn handle_peer_command(&mut self, command: PeerCommand) {
+        match command {
+            PeerCommand::UpsertDesired(mapping) => self.upsert_desired_peer(mapping).await,
+            PeerCommand::RemoveDesired(id) => self.remove_desired_peer(id).await,
+            PeerCommand::HasState(id, tx) => {
+                let _ = tx.send(self.peers.contains_key(&id));
+            }
+            PeerCommand::GetEffective(id, tx) => {
+                let _ = tx.send(self.effective_peer(id).cloned());
+            }
+        }
+    }
+
+    /// Run the client until the command channel closes or the shutdown.
+    ///
+    /// On shutdown, the commands already sent are still handled.
+    ///
+    /// The command channel is borrowed, so that it outlives the client if the loop
+    /// is cancelled.
+    pub async fn lifecycle_loop(
+        &mut self,
+        rx: &mut tokio::sync::mpsc::Receiver<Command>,
+        shutdown: impl Future<Output = ()>,
+    ) {
         let mut incoming_packet = [0; pcp_packet::LEN];
+        let mut shutdown = std::pin::pin!(shutdown);
+        let mut shutting_down = false;
 
         tracing::info!(message = "lifecycle loop started");
 
+        self.reconcile_once().await;
+
         loop {
             let next_incoming = self.transport.recv(&mut incoming_packet);
+            let next_deadline = self.sleep_until(self.next_deadline());
 
             tokio::select! {
-                _ = &mut next_keepalive => {
-                    tracing::info!(message = "keepalive timer triggered");
-                    next_keepalive = Box::pin(self.runtime.sleep(self.keepalive_interval));
+                _ = next_deadline => {
+                    tracing::debug!(message = "renewal or retransmission timer triggered");
                     self.reconcile_once().await;
                 },
                 result = next_incoming => {
@@ -238,7 +402,7 @@ where
                     };
                     tracing::info!(message = "received PCP packet", ?recv_info);
 
-                    self.apply_incoming(&incoming_packet, pcp_ip_conv::unify(recv_info.dst.ip())).await;
+                    self.apply_incoming(&incoming_packet, &recv_info).await;
                 }
                 command = rx.recv() => {
                     let Some(command) = command else {
@@ -249,13 +413,236 @@ where
 
                     self.handle_command(command).await;
                 }
+                _ = &mut shutdown, if !shutting_down => {
+                    tracing::info!(message = "shutting down, draining the commands");
+                    rx.close();
+                    shutting_down = true;
+                }
             }
+
+            entry::record_phases(&self.mappings, &self.metrics);
+            entry::record_phases(&self.peers, &self.metrics);
         }
 
         tracing::info!(message = "lifecycle loop ended");
     }
 
-    pub async fn into_lifecycle_loop(mut self, rx: tokio::sync::mpsc::Receiver<Command>) {
-        self.lifecycle_loop(rx).await
+    pub async fn into_lifecycle_loop(
+        mut self,
+        mut rx: tokio::sync::mpsc::Receiver<Command>,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        self.lifecycle_loop(&mut rx, shutdown).await
+    }
+
+    /// Check if there is nothing left to manage.
+    fn is_idle(&self) -> bool {
+        let mappings = self.mappings.values().all(|entry| entry.state.is_idle());
+        let peers = self.peers.values().all(|entry| entry.state.is_idle());
+        mappings && peers
+    }
+
+    /// Delete all of the mappings at the server.
+    ///
+    /// Waits for the server to acknowledge the deletions until the timeout passes.
+    pub async fn release_all(&mut self, timeout: std::time::Duration) {
+        let deadline = self.runtime.now() + timeout;
+        let mut incoming_packet = [0; pcp_packet::LEN];
+
+        self.probe = None;
+
+        let ids: Vec<_> = self.mappings.keys().copied().collect();
+        for id in &ids {
+            entry::remove_desired(&mut self.mappings, id);
+        }
+        let ids: Vec<_> = self.peers.keys().copied().collect();
+        for id in &ids {
+            entry::remove_desired(&mut self.peers, id);
+        }
+
+        tracing::info!(message = "releasing all of the mappings");
+
+        self.reconcile_once().await;
+
+        while !self.is_idle() {
+            let next_incoming = self.transport.recv(&mut incoming_packet);
+            let next_deadline = self.sleep_until(self.next_deadline());
+
+            tokio::select! {
+                _ = self.sleep_until(Some(deadline)) => {
+                    tracing::warn!(message = "timed out waiting for the mappings to be released");
+                    return;
+                }
+                _ = next_deadline => self.reconcile_once().await,
+                result = next_incoming => {
+                    let recv_info = match result {
+                        Ok(val) => val,
+                        Err(error) => {
+                            tracing::error!(message = "error while receiving a PCP packet", ?error);
+                            return;
+                        }
+                    };
+                    self.apply_incoming(&incoming_packet, &recv_info).await;
+                }
+            }
+        }
+
+        tracing::info!(message = "all of the mappings are released");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        net::{Ipv6Addr, SocketAddr},
+        sync::Mutex,
+        time::Instant,
+    };
+
+    #[derive(Debug)]
+    struct TestRuntime(Instant);
+
+    impl pcp_client_core::Runtime for TestRuntime {
+        type SleepFuture = std::future::Ready<()>;
+
+        fn sleep(&self, _duration: std::time::Duration) -> Self::SleepFuture {
+            std::future::ready(())
+        }
+
+        fn now(&self) -> Instant {
+            self.0
+        }
+
+        fn spawn_background(&self, _fut: impl Future<Output = ()> + Send + 'static) {}
+    }
+
+    #[derive(Debug, Default)]
+    struct TestTransport {
+        /// The opcodes of the sent requests.
+        sent: Mutex<Vec<u8>>,
+    }
+
+    impl pcp_client_core::Transport for TestTransport {
+        fn send<'a>(
+            &'a self,
+            _to: SocketAddr,
+            request: &'a [u8],
+        ) -> impl Future<Output = Result<(), std::io::Error>> + Send + 'a {
+            self.sent.lock().unwrap().push(request[1] & 0x7f);
+            std::future::ready(Ok(()))
+        }
+
+        fn recv<'a>(
+            &'a self,
+            _response: &'a mut [u8; pcp_packet::LEN],
+        ) -> impl Future<Output = Result<pcp_client_core::RecvInfo, std::io::Error>> + Send + 'a
+        {
+            std::future::pending()
+        }
+    }
+
+    const SERVER: SocketAddr = SocketAddr::new(
+        std::net::IpAddr::V6(Ipv6Addr::LOCALHOST),
+        pcp_consts::PCP_SERVER_PORT,
+    );
+
+    fn recv_info(len: usize) -> pcp_client_core::RecvInfo {
+        pcp_client_core::RecvInfo {
+            src: SERVER,
+            dst: SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 5350),
+            len,
+        }
+    }
+
+    fn header() -> pcp_codec::data::response::Header {
+        pcp_codec::data::response::Header {
+            result_code: pcp_consts::result_code::SUCCESS,
+            lifetime: 3600,
+            epoch_time: 100,
+        }
+    }
+
+    #[test]
+    fn probe_answer() {
+        let (notifications_tx, _notifications_rx) = tokio::sync::mpsc::channel(1);
+        let mut client = Client {
+            runtime: TestRuntime(Instant::now()),
+            transport: TestTransport::default(),
+            server_address: SERVER,
+            mappings: Default::default(),
+            peers: Default::default(),
+            epochs: Default::default(),
+            retransmit: Default::default(),
+            notifications_tx,
+            metrics: Default::default(),
+            health: Default::default(),
+            probe: Some(probe::Probe::new(Ipv6Addr::LOCALHOST)),
+        };
+
+        let id = mapping::Id {
+            protocol: pcp_consts::protocol::TCP,
+            internal_ip: Ipv6Addr::LOCALHOST,
+            internal_port: 80,
+            nonce: [1; 12],
+        };
+        let mapping = Mapping {
+            id,
+            params: mapping::Params {
+                lifetime: 3600,
+                external_port: 80,
+                exteranl_ip: Ipv6Addr::UNSPECIFIED,
+                third_party: None,
+                prefer_failure: None,
+                filters: None,
+            },
+        };
+
+        futures::executor::block_on(async {
+            client.upsert_desired(mapping).await;
+            assert_eq!(
+                *client.transport.sent.lock().unwrap(),
+                [pcp_consts::opcode::ANNOUNCE, pcp_consts::opcode::MAP]
+            );
+
+            // The mapping is granted, the probe is still outstanding.
+            let mut packet = [0; pcp_packet::LEN];
+            let (_, len) = pcp_codec::encode::State::new(&mut packet)
+                .response()
+                .map(
+                    header(),
+                    pcp_codec::data::response::Map {
+                        mapping_nonce: id.nonce,
+                        protocol: id.protocol,
+                        internal_port: id.internal_port,
+                        assigned_external_port: 80,
+                        assigned_external_ip_address: Ipv6Addr::LOCALHOST,
+                    },
+                )
+                .finish_with_len();
+            client.apply_incoming(&packet, &recv_info(len)).await;
+            assert!(client.health.has_answered());
+            assert!(client.probe.is_some());
+
+            let mut packet = [0; pcp_packet::LEN];
+            let (_, len) = pcp_codec::encode::State::new(&mut packet)
+                .response()
+                .announce(header())
+                .finish_with_len();
+
+            // The answer to the probe does not re-send the mappings.
+            client.apply_incoming(&packet, &recv_info(len)).await;
+            assert!(client.probe.is_none());
+            assert_eq!(client.transport.sent.lock().unwrap().len(), 2);
+
+            // The unsolicited one does.
+            client.apply_incoming(&packet, &recv_info(len)).await;
+            assert_eq!(
+                client.transport.sent.lock().unwrap().last(),
+                Some(&pcp_consts::opcode::MAP)
+            );
+            assert_eq!(client.transport.sent.lock().unwrap().len(), 3);
+        });
     }
 }
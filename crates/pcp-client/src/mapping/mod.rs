@@ -26,7 +26,7 @@ pub struct Id {
     pub nonce: Nonce,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Params {
     /// Lifetime (in seconds).
     ///
@@ -62,7 +62,7 @@ pub struct Params {
     pub filters: Option<option::Filters>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Mapping {
     /// The fields that constitute a mapping ID.
     pub id: Id,
@@ -89,6 +89,120 @@ impl Incoming {
     }
 }
 
+impl crate::request::Encode for Mapping {
+    const OPCODE: &'static str = "map";
+
+    type Response = Incoming;
+
+    fn encode<'a>(
+        &self,
+        packet: &'a mut pcp_packet::Buffer,
+        suggestion: Option<&Incoming>,
+    ) -> &'a [u8] {
+        let Self {
+            id:
+                Id {
+                    protocol,
+                    internal_ip,
+                    internal_port,
+                    nonce,
+                },
+            params:
+                Params {
+                    lifetime,
+                    external_port,
+                    exteranl_ip,
+                    third_party,
+                    prefer_failure,
+                    filters,
+                },
+        } = self;
+
+        // See <https://datatracker.ietf.org/doc/html/rfc6887#section-11.2>.
+        let (external_port, exteranl_ip) = match suggestion {
+            Some(incoming) => (
+                &incoming.packet_opcode.assigned_external_port,
+                &incoming.packet_opcode.assigned_external_ip_address,
+            ),
+            None => (external_port, exteranl_ip),
+        };
+
+        let enc = pcp_codec::encode::State::new(packet).request().map(
+            pcp_codec::data::request::Header {
+                requested_lifetime: *lifetime,
+                client_ip_address: *internal_ip,
+            },
+            pcp_codec::data::request::Map {
+                mapping_nonce: *nonce,
+                protocol: *protocol,
+                internal_port: *internal_port,
+                suggested_external_port: *external_port,
+                suggested_external_ip_address: *exteranl_ip,
+            },
+        );
+
+        let mut enc = enc.dynamic();
+
+        if let Some(third_party) = third_party {
+            enc = add_option(
+                enc,
+                third_party.wire_code(pcp_consts::option::THIRD_PARTY),
+                &third_party.payload.octets(),
+            );
+        }
+
+        if let Some(prefer_failure) = prefer_failure {
+            enc = add_option(
+                enc,
+                prefer_failure.wire_code(pcp_consts::option::PREFER_FAILURE),
+                &[],
+            );
+        }
+
+        // One option per filter.
+        //
+        // See <https://datatracker.ietf.org/doc/html/rfc6887#section-13.3>.
+        if let Some(filters) = filters {
+            let option_code = filters.wire_code(pcp_consts::option::FILTER);
+            for (remote_peer_ip, prefix_length) in &filters.payload {
+                let data = pcp_packet::option::filter::Data {
+                    reserved1: [0; 1],
+                    prefix_length: *prefix_length,
+                    remote_peer_port: pcp_consts::port::ANY.to_be_bytes(),
+                    remote_peer_ip_address: remote_peer_ip.octets(),
+                };
+                let data: &pcp_packet::option::filter::Buffer = bytemuck::must_cast_ref(&data);
+                enc = add_option(enc, option_code, data);
+            }
+        }
+
+        let (packet, len) = enc.finish_with_len();
+        &packet[..len]
+    }
+}
+
+/// Add the option to the request, skipping it if it does not fit.
+pub(crate) fn add_option<'a>(
+    enc: pcp_codec::encode::State<
+        &'a mut pcp_packet::Buffer,
+        pcp_codec::encode::steps::NeedsOptionsDynamic,
+    >,
+    option_code: OptionCode,
+    option_data: &[u8],
+) -> pcp_codec::encode::State<
+    &'a mut pcp_packet::Buffer,
+    pcp_codec::encode::steps::NeedsOptionsDynamic,
+> {
+    enc.add_option(option_code, option_data)
+        .unwrap_or_else(|enc| {
+            tracing::warn!(
+                message = "PCP option does not fit into the request, skipping",
+                option_code
+            );
+            enc
+        })
+}
+
 impl pcp_lifecycle::Mapping for Mapping {
     fn is_same_mapping_instance(&self, other: &Self) -> bool {
         self.id == other.id
@@ -103,6 +217,16 @@ impl pcp_lifecycle::Incoming<Mapping> for Incoming {
     }
 }
 
+impl pcp_lifecycle::Response for Incoming {
+    fn result_code(&self) -> ResultCode {
+        self.packet_header.result_code
+    }
+
+    fn lifetime(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.packet_header.lifetime.into())
+    }
+}
+
 impl pcp_lifecycle::cleanup::Into for Mapping {
     type Mapping = Self;
 
@@ -1,6 +1,6 @@
-use super::{Address, PrefixLength};
+use super::{Address, OptionCode, PrefixLength};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PcpOption<T> {
     /// Is the handling of this option optional.
     ///
@@ -13,6 +13,17 @@ pub struct PcpOption<T> {
     pub payload: T,
 }
 
+impl<T> PcpOption<T> {
+    /// The option code to put on the wire for the option with the given code.
+    pub fn wire_code(&self, option_code: OptionCode) -> OptionCode {
+        if self.is_optional {
+            option_code | 0b1000_0000
+        } else {
+            option_code
+        }
+    }
+}
+
 pub type ThirdParty = PcpOption<Address>;
 pub type PreferFailure = PcpOption<()>;
 
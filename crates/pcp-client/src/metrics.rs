@@ -0,0 +1,134 @@
+//! The client metrics.
+
+use metrics::{Counter, Encoder, Family, Gauge, Histogram, Type};
+
+use crate::{dropped, entry};
+
+/// The metrics of a single client.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The requests sent, by opcode.
+    pub requests_sent: Family<&'static str, Counter>,
+
+    /// The requests sent again for the lack of a response, by opcode.
+    pub retransmissions: Family<&'static str, Counter>,
+
+    /// The responses received, by opcode and result code.
+    pub responses_received: Family<(&'static str, String), Counter>,
+
+    /// The time from the first transmission of a request to the successful response.
+    pub renewal_latency: Histogram,
+
+    /// The mappings, by opcode and lifecycle phase.
+    pub mappings: Family<(&'static str, entry::Phase), Gauge>,
+
+    /// The dropped incoming packets.
+    pub dropped: dropped::Counters,
+}
+
+impl Metrics {
+    /// Count the response with the result code.
+    pub fn record_response(&self, opcode: &'static str, result_code: pcp_primitives::ResultCode) {
+        let result_code = pcp_consts::result_code::name(result_code)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| result_code.to_string());
+        self.responses_received
+            .with((opcode, result_code), Counter::inc);
+    }
+}
+
+/// Encode the metrics of the clients, labeled by the PCP server address.
+pub fn encode(encoder: &mut Encoder, clients: &[(String, &Metrics)]) {
+    let name = "pcp_client_requests_sent_total";
+    encoder.header(name, "The PCP requests sent.", Type::Counter);
+    for (server, metrics) in clients {
+        metrics.requests_sent.for_each(|opcode, counter| {
+            encoder.sample(
+                name,
+                &[("server", server), ("opcode", opcode)],
+                counter.get(),
+            )
+        });
+    }
+
+    let name = "pcp_client_retransmissions_total";
+    encoder.header(name, "The PCP requests retransmitted.", Type::Counter);
+    for (server, metrics) in clients {
+        metrics.retransmissions.for_each(|opcode, counter| {
+            encoder.sample(
+                name,
+                &[("server", server), ("opcode", opcode)],
+                counter.get(),
+            )
+        });
+    }
+
+    let name = "pcp_client_responses_received_total";
+    encoder.header(name, "The PCP responses received.", Type::Counter);
+    for (server, metrics) in clients {
+        metrics
+            .responses_received
+            .for_each(|(opcode, result_code), counter| {
+                let labels = [
+                    ("server", server.as_str()),
+                    ("opcode", opcode),
+                    ("result_code", result_code),
+                ];
+                encoder.sample(name, &labels, counter.get())
+            });
+    }
+
+    let name = "pcp_client_renewal_latency_seconds";
+    encoder.header(
+        name,
+        "The time from the first transmission of a request to the successful response.",
+        Type::Histogram,
+    );
+    for (server, metrics) in clients {
+        encoder.histogram(name, &[("server", server)], &metrics.renewal_latency);
+    }
+
+    let name = "pcp_client_mappings";
+    encoder.header(name, "The mappings by lifecycle phase.", Type::Gauge);
+    for (server, metrics) in clients {
+        metrics.mappings.for_each(|(opcode, phase), gauge| {
+            let labels = [
+                ("server", server.as_str()),
+                ("opcode", opcode),
+                ("phase", phase.as_str()),
+            ];
+            encoder.sample(name, &labels, gauge.get())
+        });
+    }
+
+    let name = "pcp_client_dropped_packets_total";
+    encoder.header(name, "The incoming PCP packets dropped.", Type::Counter);
+    for (server, metrics) in clients {
+        for reason in dropped::Reason::ALL {
+            let labels = [("server", server.as_str()), ("reason", reason.as_str())];
+            encoder.sample(name, &labels, metrics.dropped.get(reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn result_code_labels() {
+        let metrics = Metrics::default();
+        metrics.record_response("map", pcp_consts::result_code::SUCCESS);
+        metrics.record_response("map", pcp_consts::result_code::SUCCESS);
+        metrics.record_response("map", 200);
+
+        let mut labels = Vec::new();
+        metrics
+            .responses_received
+            .for_each(|(_, result_code), counter| {
+                labels.push((result_code.clone(), counter.get()))
+            });
+
+        assert_eq!(labels, [("200".to_owned(), 1), ("SUCCESS".to_owned(), 2)]);
+    }
+}
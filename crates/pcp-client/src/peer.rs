@@ -0,0 +1,255 @@
+//! `PEER` mappings.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-12>.
+
+use pcp_primitives::*;
+
+use crate::mapping::{add_option, option};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Id {
+    /// Protocol.
+    ///
+    /// Specified by the client, server must acceept or reject with `UNSUPP_PROTOCOL`.
+    pub protocol: Protocol,
+
+    /// Internal IP.
+    ///
+    /// Specified by the client, but can't be chosen freely as the server will check the packets
+    /// it is receiving are sent from this `src`, and will also send the reply to that packet.
+    pub internal_ip: Address,
+
+    /// Internal port.
+    ///
+    /// Specified by the client, identifies the existing outbound connection.
+    pub internal_port: Port,
+
+    /// Remote peer IP.
+    ///
+    /// Specified by the client, identifies the existing outbound connection.
+    pub remote_peer_ip: Address,
+
+    /// Remote peer port.
+    ///
+    /// Specified by the client, identifies the existing outbound connection.
+    pub remote_peer_port: Port,
+
+    /// The mapping nonce.
+    ///
+    /// A unique random sequence of bytes.
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Params {
+    /// Lifetime (in seconds).
+    ///
+    /// Specified by the client.
+    pub lifetime: LifetimeSeconds,
+
+    /// External port.
+    ///
+    /// Specified by the server, but the client can suggest the port the connection
+    /// is already using.
+    pub external_port: Port,
+
+    /// External IP.
+    ///
+    /// Specified by the server, but the client can suggest the IP address the connection
+    /// is already using.
+    pub external_ip: Address,
+
+    /// Third Party option.
+    ///
+    /// Indicates this mapping does not have the Client IP as its Internal IP.
+    ///
+    /// This MUST NOT be implemented without a separate authorization.
+    pub third_party: Option<option::ThirdParty>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Mapping {
+    /// The fields that constitute a mapping ID.
+    pub id: Id,
+
+    /// The fields that are only used for selecting mapping params.
+    pub params: Params,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Incoming {
+    pub received_on: Address,
+    pub packet_header: pcp_codec::data::response::Header,
+    pub packet_opcode: pcp_codec::data::response::Peer,
+}
+
+impl Incoming {
+    pub fn id(&self) -> Id {
+        Id {
+            protocol: self.packet_opcode.protocol,
+            internal_ip: self.received_on,
+            internal_port: self.packet_opcode.internal_port,
+            remote_peer_ip: self.packet_opcode.remote_peer_ip_address,
+            remote_peer_port: self.packet_opcode.remote_peer_port,
+            nonce: self.packet_opcode.mapping_nonce,
+        }
+    }
+}
+
+impl crate::request::Encode for Mapping {
+    const OPCODE: &'static str = "peer";
+
+    type Response = Incoming;
+
+    fn encode<'a>(
+        &self,
+        packet: &'a mut pcp_packet::Buffer,
+        suggestion: Option<&Incoming>,
+    ) -> &'a [u8] {
+        let Self {
+            id:
+                Id {
+                    protocol,
+                    internal_ip,
+                    internal_port,
+                    remote_peer_ip,
+                    remote_peer_port,
+                    nonce,
+                },
+            params:
+                Params {
+                    lifetime,
+                    external_port,
+                    external_ip,
+                    third_party,
+                },
+        } = self;
+
+        // See <https://datatracker.ietf.org/doc/html/rfc6887#section-11.2>.
+        let (external_port, external_ip) = match suggestion {
+            Some(incoming) => (
+                &incoming.packet_opcode.assigned_external_port,
+                &incoming.packet_opcode.assigned_external_ip_address,
+            ),
+            None => (external_port, external_ip),
+        };
+
+        let enc = pcp_codec::encode::State::new(packet).request().peer(
+            pcp_codec::data::request::Header {
+                requested_lifetime: *lifetime,
+                client_ip_address: *internal_ip,
+            },
+            pcp_codec::data::request::Peer {
+                mapping_nonce: *nonce,
+                protocol: *protocol,
+                internal_port: *internal_port,
+                suggested_external_port: *external_port,
+                suggested_external_ip_address: *external_ip,
+                remote_peer_port: *remote_peer_port,
+                remote_peer_ip_address: *remote_peer_ip,
+            },
+        );
+
+        let mut enc = enc.dynamic();
+
+        if let Some(third_party) = third_party {
+            enc = add_option(
+                enc,
+                third_party.wire_code(pcp_consts::option::THIRD_PARTY),
+                &third_party.payload.octets(),
+            );
+        }
+
+        let (packet, len) = enc.finish_with_len();
+        &packet[..len]
+    }
+}
+
+impl pcp_lifecycle::Mapping for Mapping {
+    fn is_same_mapping_instance(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl pcp_lifecycle::Incoming<Mapping> for Incoming {
+    fn is_same_exposed_resource(&self, other: &Mapping) -> bool {
+        self.received_on == other.id.internal_ip
+            && self.packet_opcode.protocol == other.id.protocol
+            && self.packet_opcode.internal_port == other.id.internal_port
+            && self.packet_opcode.remote_peer_ip_address == other.id.remote_peer_ip
+            && self.packet_opcode.remote_peer_port == other.id.remote_peer_port
+    }
+}
+
+impl pcp_lifecycle::Response for Incoming {
+    fn result_code(&self) -> ResultCode {
+        self.packet_header.result_code
+    }
+
+    fn lifetime(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.packet_header.lifetime.into())
+    }
+}
+
+impl pcp_lifecycle::cleanup::Into for Mapping {
+    type Mapping = Self;
+
+    fn into_cleanup(mut self) -> Self::Mapping {
+        self.params.lifetime = 0;
+        self
+    }
+}
+
+impl pcp_lifecycle::cleanup::Maybe for Incoming {
+    fn is_cleanup(&self) -> bool {
+        self.packet_header.result_code == pcp_consts::result_code::SUCCESS
+            && self.packet_header.lifetime == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::request::Encode as _;
+
+    #[test]
+    fn third_party() {
+        let mut mapping = Mapping {
+            id: Id {
+                protocol: pcp_consts::protocol::TCP,
+                internal_ip: std::net::Ipv6Addr::LOCALHOST,
+                internal_port: 50000,
+                remote_peer_ip: std::net::Ipv4Addr::new(203, 0, 113, 1).to_ipv6_mapped(),
+                remote_peer_port: 443,
+                nonce: [1; 12],
+            },
+            params: Params {
+                lifetime: 3600,
+                external_port: 0,
+                external_ip: Address::UNSPECIFIED,
+                third_party: None,
+            },
+        };
+
+        let mut packet = [0; pcp_packet::LEN];
+        let len = mapping.encode(&mut packet, None).len();
+        assert_eq!(len, pcp_packet::header::LEN + pcp_packet::opcode::peer::LEN);
+
+        let internal_ip = std::net::Ipv4Addr::new(192, 0, 2, 10).to_ipv6_mapped();
+        mapping.params.third_party = Some(option::PcpOption {
+            is_optional: false,
+            payload: internal_ip,
+        });
+
+        let mut packet = [0; pcp_packet::LEN];
+        let encoded = mapping.encode(&mut packet, None);
+        let option = &encoded[len..];
+        assert_eq!(option.len(), pcp_packet::option::header::LEN + 16);
+        assert_eq!(option[0], pcp_consts::option::THIRD_PARTY);
+        assert_eq!(
+            option[pcp_packet::option::header::LEN..],
+            internal_ip.octets()
+        );
+    }
+}
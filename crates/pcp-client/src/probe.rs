@@ -0,0 +1,85 @@
+//! Server reachability probing.
+//!
+//! The `ANNOUNCE` request is sent until the server answers anything, so that we know
+//! the server is reachable even if there are no mappings to request.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-14.1>.
+
+use std::time::Instant;
+
+use crate::retransmit;
+
+/// The outstanding `ANNOUNCE` request.
+#[derive(Debug)]
+pub struct Probe {
+    /// The address of the client to send the request for.
+    pub client_ip_address: pcp_primitives::Address,
+
+    /// The retransmission schedule, `None` if the request is yet to be sent.
+    retransmission: Option<retransmit::Schedule>,
+}
+
+impl Probe {
+    /// Create a new [`Probe`] that is to be sent right away.
+    pub fn new(client_ip_address: pcp_primitives::Address) -> Self {
+        Self {
+            client_ip_address,
+            retransmission: None,
+        }
+    }
+
+    /// The time at which the request is to be sent.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.retransmission.map(|schedule| schedule.deadline())
+    }
+
+    /// Schedule the next transmission if the request is due at the given time.
+    ///
+    /// Returns `true` if the request is to be sent now.
+    pub fn advance(&mut self, retransmit: &retransmit::Params, now: Instant) -> bool {
+        let rand = retransmit::random_factor();
+        self.retransmission = match self.retransmission {
+            Some(schedule) if schedule.deadline() > now => return false,
+            // Keep probing at the maximum retransmission timeout instead of giving up.
+            Some(schedule) => schedule
+                .advance(retransmit, now, rand)
+                .or_else(|| Some(retransmit::Schedule::start(retransmit, now, rand))),
+            None => Some(retransmit::Schedule::start(retransmit, now, rand)),
+        };
+        true
+    }
+
+    /// Encode the request into the given packet buffer.
+    pub fn encode<'a>(&self, packet: &'a mut pcp_packet::Buffer) -> &'a [u8] {
+        let enc = pcp_codec::encode::State::new(packet).request().announce(
+            pcp_codec::data::request::Header {
+                requested_lifetime: 0,
+                client_ip_address: self.client_ip_address,
+            },
+        );
+        let (packet, len) = enc.finish_with_len();
+        &packet[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let params = retransmit::Params::default();
+        let now = Instant::now();
+
+        let mut probe = Probe::new(std::net::Ipv6Addr::LOCALHOST);
+        assert_eq!(probe.deadline(), None);
+
+        assert!(probe.advance(&params, now));
+        let deadline = probe.deadline().unwrap();
+        assert!(deadline > now);
+
+        assert!(!probe.advance(&params, now));
+        assert!(probe.advance(&params, deadline));
+        assert!(probe.deadline().unwrap() > deadline);
+    }
+}
@@ -0,0 +1,22 @@
+//! Request encoding.
+
+/// A mapping that can be encoded into a PCP request packet.
+pub trait Encode {
+    /// The name of the request opcode, in `snake_case`.
+    const OPCODE: &'static str;
+
+    /// The response to the request.
+    type Response;
+
+    /// Encode the request into the given packet buffer.
+    ///
+    /// The external address and port of the `suggestion` are suggested instead of
+    /// the requested ones, to keep the external endpoint stable across the renewals.
+    ///
+    /// Returns the encoded part of the buffer.
+    fn encode<'a>(
+        &self,
+        packet: &'a mut pcp_packet::Buffer,
+        suggestion: Option<&Self::Response>,
+    ) -> &'a [u8];
+}
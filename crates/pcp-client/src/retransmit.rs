@@ -0,0 +1,194 @@
+//! Request retransmission.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-8.1.1>.
+
+use std::time::{Duration, Instant};
+
+/// The maximum magnitude of the random factor applied to the retransmission timeouts.
+pub const JITTER: f64 = 0.1;
+
+/// Retransmission parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+    /// Initial retransmission time (`IRT`).
+    pub initial_timeout: Duration,
+
+    /// Maximum retransmission count (`MRC`).
+    ///
+    /// The total number of transmissions after which we give up, `None` for unlimited.
+    pub max_count: Option<u32>,
+
+    /// Maximum retransmission time (`MRT`).
+    pub max_timeout: Duration,
+
+    /// Maximum retransmission duration (`MRD`).
+    ///
+    /// The time since the first transmission after which we give up, `None` for unlimited.
+    pub max_duration: Option<Duration>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            initial_timeout: Duration::from_secs(3),
+            max_count: None,
+            max_timeout: Duration::from_secs(1024),
+            max_duration: None,
+        }
+    }
+}
+
+/// The retransmission schedule of an outstanding request.
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    /// The time of the first transmission.
+    started_at: Instant,
+
+    /// The number of transmissions so far.
+    count: u32,
+
+    /// The current retransmission timeout (`RT`).
+    timeout: Duration,
+
+    /// The time at which the next retransmission is due.
+    deadline: Instant,
+}
+
+impl Schedule {
+    /// Start the schedule for a request that has just been transmitted for the first time.
+    ///
+    /// The `rand` is the random factor in the `[-JITTER, JITTER]` range.
+    pub fn start(params: &Params, now: Instant, rand: f64) -> Self {
+        let timeout = params.initial_timeout.mul_f64(1.0 + rand);
+        Self {
+            started_at: now,
+            count: 1,
+            timeout,
+            deadline: now + timeout,
+        }
+    }
+
+    /// The time of the first transmission.
+    pub fn started_at(&self) -> Instant {
+        self.started_at
+    }
+
+    /// The time at which the next retransmission is due.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Advance the schedule for the retransmission that is due.
+    ///
+    /// Returns `None` if we should give up retransmitting instead.
+    pub fn advance(self, params: &Params, now: Instant, rand: f64) -> Option<Self> {
+        if let Some(max_count) = params.max_count {
+            if self.count >= max_count {
+                return None;
+            }
+        }
+
+        if let Some(max_duration) = params.max_duration {
+            if now.saturating_duration_since(self.started_at) >= max_duration {
+                return None;
+            }
+        }
+
+        let timeout = self
+            .timeout
+            .saturating_mul(2)
+            .min(params.max_timeout)
+            .mul_f64(1.0 + rand);
+
+        Some(Self {
+            started_at: self.started_at,
+            count: self.count + 1,
+            timeout,
+            deadline: now + timeout,
+        })
+    }
+}
+
+/// Generate a random factor for the retransmission timeouts.
+pub fn random_factor() -> f64 {
+    use rand::Rng as _;
+    rand::thread_rng().gen_range(-JITTER..=JITTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let params = Params::default();
+        let start = Instant::now();
+
+        let schedule = Schedule::start(&params, start, 0.0);
+        assert_eq!(schedule.deadline(), start + Duration::from_secs(3));
+
+        let mut now = schedule.deadline();
+        let mut schedule = schedule;
+        let mut timeouts = Vec::new();
+        for _ in 0..10 {
+            schedule = schedule.advance(&params, now, 0.0).unwrap();
+            timeouts.push(schedule.deadline() - now);
+            now = schedule.deadline();
+        }
+
+        assert_eq!(
+            timeouts,
+            [6, 12, 24, 48, 96, 192, 384, 768, 1024, 1024].map(Duration::from_secs)
+        );
+    }
+
+    #[test]
+    fn jitter() {
+        let params = Params::default();
+        let start = Instant::now();
+
+        let schedule = Schedule::start(&params, start, JITTER);
+        assert_eq!(schedule.deadline(), start + Duration::from_millis(3300));
+
+        let schedule = Schedule::start(&params, start, -JITTER);
+        assert_eq!(schedule.deadline(), start + Duration::from_millis(2700));
+
+        for _ in 0..100 {
+            let rand = random_factor();
+            assert!((-JITTER..=JITTER).contains(&rand));
+        }
+    }
+
+    #[test]
+    fn max_count() {
+        let params = Params {
+            max_count: Some(3),
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        let schedule = Schedule::start(&params, start, 0.0);
+        let schedule = schedule.advance(&params, schedule.deadline(), 0.0).unwrap();
+        let schedule = schedule.advance(&params, schedule.deadline(), 0.0).unwrap();
+        assert!(schedule
+            .advance(&params, schedule.deadline(), 0.0)
+            .is_none());
+    }
+
+    #[test]
+    fn max_duration() {
+        let params = Params {
+            max_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        // Transmissions at 0s, 3s and 9s, giving up at 21s.
+        let schedule = Schedule::start(&params, start, 0.0);
+        let schedule = schedule.advance(&params, schedule.deadline(), 0.0).unwrap();
+        let schedule = schedule.advance(&params, schedule.deadline(), 0.0).unwrap();
+        assert!(schedule
+            .advance(&params, schedule.deadline(), 0.0)
+            .is_none());
+    }
+}
@@ -15,6 +15,17 @@ pub mod request {
         pub suggested_external_port: Port,
         pub suggested_external_ip_address: Address,
     }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Peer {
+        pub mapping_nonce: Nonce,
+        pub protocol: Protocol,
+        pub internal_port: Port,
+        pub suggested_external_port: Port,
+        pub suggested_external_ip_address: Address,
+        pub remote_peer_port: Port,
+        pub remote_peer_ip_address: Address,
+    }
 }
 
 pub mod response {
@@ -35,4 +46,31 @@ pub mod response {
         pub assigned_external_port: Port,
         pub assigned_external_ip_address: Address,
     }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Peer {
+        pub mapping_nonce: Nonce,
+        pub protocol: Protocol,
+        pub internal_port: Port,
+        pub assigned_external_port: Port,
+        pub assigned_external_ip_address: Address,
+        pub remote_peer_port: Port,
+        pub remote_peer_ip_address: Address,
+    }
+}
+
+pub mod option {
+    use pcp_primitives::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct ThirdParty {
+        pub internal_ip_address: Address,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Filter {
+        pub prefix_length: PrefixLength,
+        pub remote_peer_port: Port,
+        pub remote_peer_ip_address: Address,
+    }
 }
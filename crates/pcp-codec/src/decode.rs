@@ -1,4 +1,5 @@
 pub mod check;
+pub mod options;
 
 use const_sub_array::SubArray;
 
@@ -6,11 +7,32 @@ use crate::data;
 
 pub struct State<'p> {
     packet: &'p pcp_packet::Buffer,
+    len: usize,
 }
 
 impl<'p> State<'p> {
+    /// Decode the packet that occupies the whole buffer.
     pub fn new(packet: &'p pcp_packet::Buffer) -> Self {
-        Self { packet }
+        Self::with_len(packet, pcp_packet::LEN)
+    }
+
+    /// Decode the packet that occupies only the first `len` bytes of the buffer.
+    ///
+    /// The rest of the buffer is expected to be zeroed.
+    pub fn with_len(packet: &'p pcp_packet::Buffer, len: usize) -> Self {
+        let len = len.min(pcp_packet::LEN);
+        Self { packet, len }
+    }
+
+    /// The length of the packet.
+    pub fn packet_len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the packet is long enough to contain the header and the opcode data
+    /// of the given length.
+    fn has_opcode_data(&self, opcode_data_len: usize) -> bool {
+        self.len >= pcp_packet::header::LEN + opcode_data_len
     }
 
     pub fn meta(&self) -> &'p pcp_packet::Meta {
@@ -37,14 +59,81 @@ impl<'p> State<'p> {
         bytemuck::must_cast_ref(output)
     }
 
-    pub fn map_request_data(&self) -> Option<(data::request::Header, data::request::Map)> {
+    /// Iterate over the options that follow the opcode data.
+    pub fn options(&self) -> options::Options<'p> {
+        let opcode_data_len = match self.meta().r_and_opcode.opcode() {
+            pcp_consts::opcode::ANNOUNCE => 0,
+            pcp_consts::opcode::MAP => pcp_packet::opcode::map::LEN,
+            pcp_consts::opcode::PEER => pcp_packet::opcode::peer::LEN,
+            _ => return options::Options::error(options::Error::UnknownOpcode),
+        };
+
+        let offset = pcp_packet::header::LEN + opcode_data_len;
+        let Some(data) = self.packet.get(offset..self.len) else {
+            return options::Options::error(options::Error::Truncated { offset });
+        };
+
+        options::Options::new(data, offset)
+    }
+
+    fn request_header_data(
+        &self,
+        expected_opcode: pcp_primitives::Opcode,
+    ) -> Option<data::request::Header> {
         let pcp_packet::header::Request {
             meta,
             reserved1: _,
             requested_lifetime,
             client_ip_address,
         } = self.header_unchecked();
-        if !check::meta(meta, false, pcp_consts::opcode::MAP) {
+        if !self.has_opcode_data(0) || !check::meta(meta, false, expected_opcode) {
+            return None;
+        }
+
+        use pcp_primitives::{Address, LifetimeSeconds};
+
+        Some(data::request::Header {
+            requested_lifetime: LifetimeSeconds::from_be_bytes(*requested_lifetime),
+            client_ip_address: Address::from(*client_ip_address),
+        })
+    }
+
+    fn response_header_data(
+        &self,
+        expected_opcode: pcp_primitives::Opcode,
+    ) -> Option<data::response::Header> {
+        let pcp_packet::header::Response {
+            meta,
+            reserved1: _,
+            result_code,
+            lifetime,
+            epoch_time,
+            reserved2: _,
+        } = self.header_unchecked();
+        if !self.has_opcode_data(0) || !check::meta(meta, true, expected_opcode) {
+            return None;
+        }
+
+        use pcp_primitives::{EpochTime, LifetimeSeconds};
+
+        Some(data::response::Header {
+            result_code: *result_code,
+            lifetime: LifetimeSeconds::from_be_bytes(*lifetime),
+            epoch_time: EpochTime::from_be_bytes(*epoch_time),
+        })
+    }
+
+    pub fn announce_request_data(&self) -> Option<data::request::Header> {
+        self.request_header_data(pcp_consts::opcode::ANNOUNCE)
+    }
+
+    pub fn announce_response_data(&self) -> Option<data::response::Header> {
+        self.response_header_data(pcp_consts::opcode::ANNOUNCE)
+    }
+
+    pub fn map_request_data(&self) -> Option<(data::request::Header, data::request::Map)> {
+        let header = self.request_header_data(pcp_consts::opcode::MAP)?;
+        if !self.has_opcode_data(pcp_packet::opcode::map::LEN) {
             return None;
         }
 
@@ -57,12 +146,8 @@ impl<'p> State<'p> {
             suggested_external_ip_address,
         } = self.opcode_unchecked();
 
-        use pcp_primitives::{Address, LifetimeSeconds, Port};
+        use pcp_primitives::{Address, Port};
 
-        let header = data::request::Header {
-            requested_lifetime: LifetimeSeconds::from_be_bytes(*requested_lifetime),
-            client_ip_address: Address::from(*client_ip_address),
-        };
         let data = data::request::Map {
             mapping_nonce: *mapping_nonce,
             protocol: *protocol,
@@ -75,15 +160,8 @@ impl<'p> State<'p> {
     }
 
     pub fn map_response_data(&self) -> Option<(data::response::Header, data::response::Map)> {
-        let pcp_packet::header::Response {
-            meta,
-            reserved1: _,
-            result_code,
-            lifetime,
-            epoch_time,
-            reserved2: _,
-        } = self.header_unchecked();
-        if !check::meta(meta, true, pcp_consts::opcode::MAP) {
+        let header = self.response_header_data(pcp_consts::opcode::MAP)?;
+        if !self.has_opcode_data(pcp_packet::opcode::map::LEN) {
             return None;
         }
 
@@ -96,13 +174,8 @@ impl<'p> State<'p> {
             assigned_external_ip_address,
         } = self.opcode_unchecked();
 
-        use pcp_primitives::{Address, EpochTime, LifetimeSeconds, Port};
+        use pcp_primitives::{Address, Port};
 
-        let header = data::response::Header {
-            result_code: *result_code,
-            lifetime: LifetimeSeconds::from_be_bytes(*lifetime),
-            epoch_time: EpochTime::from_be_bytes(*epoch_time),
-        };
         let data = data::response::Map {
             mapping_nonce: *mapping_nonce,
             protocol: *protocol,
@@ -113,4 +186,70 @@ impl<'p> State<'p> {
 
         Some((header, data))
     }
+
+    pub fn peer_request_data(&self) -> Option<(data::request::Header, data::request::Peer)> {
+        let header = self.request_header_data(pcp_consts::opcode::PEER)?;
+        if !self.has_opcode_data(pcp_packet::opcode::peer::LEN) {
+            return None;
+        }
+
+        let pcp_packet::opcode::peer::Request {
+            mapping_nonce,
+            protocol,
+            reserved1: _,
+            internal_port,
+            suggested_external_port,
+            suggested_external_ip_address,
+            remote_peer_port,
+            reserved2: _,
+            remote_peer_ip_address,
+        } = self.opcode_unchecked();
+
+        use pcp_primitives::{Address, Port};
+
+        let data = data::request::Peer {
+            mapping_nonce: *mapping_nonce,
+            protocol: *protocol,
+            internal_port: Port::from_be_bytes(*internal_port),
+            suggested_external_port: Port::from_be_bytes(*suggested_external_port),
+            suggested_external_ip_address: Address::from(*suggested_external_ip_address),
+            remote_peer_port: Port::from_be_bytes(*remote_peer_port),
+            remote_peer_ip_address: Address::from(*remote_peer_ip_address),
+        };
+
+        Some((header, data))
+    }
+
+    pub fn peer_response_data(&self) -> Option<(data::response::Header, data::response::Peer)> {
+        let header = self.response_header_data(pcp_consts::opcode::PEER)?;
+        if !self.has_opcode_data(pcp_packet::opcode::peer::LEN) {
+            return None;
+        }
+
+        let pcp_packet::opcode::peer::Response {
+            mapping_nonce,
+            protocol,
+            reserved1: _,
+            internal_port,
+            assigned_external_port,
+            assigned_external_ip_address,
+            remote_peer_port,
+            reserved2: _,
+            remote_peer_ip_address,
+        } = self.opcode_unchecked();
+
+        use pcp_primitives::{Address, Port};
+
+        let data = data::response::Peer {
+            mapping_nonce: *mapping_nonce,
+            protocol: *protocol,
+            internal_port: Port::from_be_bytes(*internal_port),
+            assigned_external_port: Port::from_be_bytes(*assigned_external_port),
+            assigned_external_ip_address: Address::from(*assigned_external_ip_address),
+            remote_peer_port: Port::from_be_bytes(*remote_peer_port),
+            remote_peer_ip_address: Address::from(*remote_peer_ip_address),
+        };
+
+        Some((header, data))
+    }
 }
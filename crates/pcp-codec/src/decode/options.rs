@@ -0,0 +1,182 @@
+//! Options decoding.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-7.3>.
+
+use pcp_primitives::{Address, OptionCode, Port};
+
+use crate::data;
+
+/// A decoded option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded<'p> {
+    /// The option code.
+    pub code: OptionCode,
+
+    /// The option value.
+    pub value: Value<'p>,
+}
+
+/// The value of a decoded option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'p> {
+    ThirdParty(data::option::ThirdParty),
+    PreferFailure,
+    Filter(data::option::Filter),
+
+    /// An option in the optional-to-process range that we do not recognize,
+    /// with its data without the padding.
+    Unknown(&'p [u8]),
+}
+
+/// An error that stops the options decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The opcode of the packet is not known, so the options can't be located.
+    UnknownOpcode,
+
+    /// The option header or the padded option data does not fit into the packet.
+    Truncated { offset: usize },
+
+    /// The option length does not match the option.
+    InvalidLength { code: OptionCode, length: usize },
+
+    /// An option in the mandatory-to-process range that we do not recognize.
+    UnknownMandatory { code: OptionCode },
+}
+
+/// The iterator over the options of a packet.
+///
+/// Stops after the first error.
+#[derive(Debug, Clone)]
+pub struct Options<'p> {
+    /// The packet part that the options occupy.
+    data: &'p [u8],
+
+    /// The offset of the next option header in the whole packet.
+    offset: usize,
+
+    /// The error to yield before stopping.
+    error: Option<Error>,
+}
+
+impl<'p> Options<'p> {
+    /// Iterate over the options in the given packet part, that starts at the `offset`
+    /// of the whole packet.
+    pub(super) fn new(data: &'p [u8], offset: usize) -> Self {
+        Self {
+            data,
+            offset,
+            error: None,
+        }
+    }
+
+    /// An iterator that only yields the given error.
+    pub(super) fn error(error: Error) -> Self {
+        Self {
+            data: &[],
+            offset: 0,
+            error: Some(error),
+        }
+    }
+
+    fn next_option(&mut self) -> Result<Decoded<'p>, Error> {
+        let truncated = Error::Truncated {
+            offset: self.offset,
+        };
+
+        let (header, rest) = self
+            .data
+            .split_first_chunk::<{ pcp_packet::option::header::LEN }>()
+            .ok_or(truncated)?;
+
+        let pcp_packet::option::header::Data {
+            option_code: code,
+            reserved1: _,
+            option_length,
+        } = bytemuck::must_cast(*header);
+        let length = usize::from(u16::from_be_bytes(option_length));
+
+        let padded_length = pcp_packet::padded_len(length);
+        if rest.len() < padded_length {
+            return Err(truncated);
+        }
+        let (padded_data, rest) = rest.split_at(padded_length);
+        let data = &padded_data[..length];
+
+        let value = decode_value(code, data)?;
+
+        self.data = rest;
+        self.offset += pcp_packet::option::header::LEN + padded_length;
+
+        Ok(Decoded { code, value })
+    }
+}
+
+impl<'p> Iterator for Options<'p> {
+    type Item = Result<Decoded<'p>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            self.data = &[];
+            return Some(Err(error));
+        }
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let result = self.next_option();
+        if result.is_err() {
+            self.data = &[];
+        }
+        Some(result)
+    }
+}
+
+/// Decode the value of the option with the given code.
+fn decode_value(code: OptionCode, data: &[u8]) -> Result<Value<'_>, Error> {
+    let invalid_length = Error::InvalidLength {
+        code,
+        length: data.len(),
+    };
+
+    Ok(match code {
+        pcp_consts::option::THIRD_PARTY => {
+            let data: &pcp_packet::option::third_party::Buffer =
+                data.try_into().map_err(|_| invalid_length)?;
+            let pcp_packet::option::third_party::Data {
+                internal_ip_address,
+            } = bytemuck::must_cast(*data);
+
+            Value::ThirdParty(data::option::ThirdParty {
+                internal_ip_address: Address::from(internal_ip_address),
+            })
+        }
+        pcp_consts::option::PREFER_FAILURE => {
+            if !data.is_empty() {
+                return Err(invalid_length);
+            }
+            Value::PreferFailure
+        }
+        pcp_consts::option::FILTER => {
+            let data: &pcp_packet::option::filter::Buffer =
+                data.try_into().map_err(|_| invalid_length)?;
+            let pcp_packet::option::filter::Data {
+                reserved1: _,
+                prefix_length,
+                remote_peer_port,
+                remote_peer_ip_address,
+            } = bytemuck::must_cast(*data);
+
+            Value::Filter(data::option::Filter {
+                prefix_length,
+                remote_peer_port: Port::from_be_bytes(remote_peer_port),
+                remote_peer_ip_address: Address::from(remote_peer_ip_address),
+            })
+        }
+        code if pcp_consts::option::is_mandatory(code) => {
+            return Err(Error::UnknownMandatory { code })
+        }
+        _ => Value::Unknown(data),
+    })
+}
@@ -1,4 +1,5 @@
 mod needs_options;
+mod needs_options_dynamic;
 mod needs_r;
 mod needs_request_opcode;
 mod needs_response_opcode;
@@ -20,6 +21,12 @@ pub mod steps {
 
     #[derive(Debug)]
     pub struct NeedsOptions<const NEXT_OPTION_OFFSET: usize>;
+
+    /// Like [`NeedsOptions`], but for when the options are only known at runtime.
+    #[derive(Debug)]
+    pub struct NeedsOptionsDynamic {
+        pub(super) next_option_offset: usize,
+    }
 }
 
 impl<Packet, Step> core::fmt::Debug for State<Packet, Step>
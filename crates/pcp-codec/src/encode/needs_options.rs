@@ -15,7 +15,11 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>, const NEXT_OPTION_OFFSET: usize>
     ) -> State<
         Packet,
         steps::NeedsOptions<
-            { NEXT_OPTION_OFFSET + pcp_packet::option::header::LEN + OPTION_DATA_LEN },
+            {
+                NEXT_OPTION_OFFSET
+                    + pcp_packet::option::header::LEN
+                    + pcp_packet::padded_len(OPTION_DATA_LEN)
+            },
         >,
     > {
         let Self {
@@ -50,7 +54,35 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>, const NEXT_OPTION_OFFSET: usize>
         }
     }
 
+    /// Switch to adding the options that are only known at runtime.
+    pub fn dynamic(self) -> State<Packet, steps::NeedsOptionsDynamic> {
+        let Self {
+            step: steps::NeedsOptions,
+            packet,
+        } = self;
+
+        State {
+            step: steps::NeedsOptionsDynamic {
+                next_option_offset: NEXT_OPTION_OFFSET,
+            },
+            packet,
+        }
+    }
+
+    /// The length of the packet encoded so far.
+    ///
+    /// Only this many bytes of the packet buffer are to be sent.
+    pub const fn packet_len(&self) -> usize {
+        NEXT_OPTION_OFFSET
+    }
+
     pub fn finish(self) -> Packet {
         self.packet
     }
+
+    /// Finish the encoding, also returning the length of the encoded packet.
+    pub fn finish_with_len(self) -> (Packet, usize) {
+        let len = self.packet_len();
+        (self.finish(), len)
+    }
 }
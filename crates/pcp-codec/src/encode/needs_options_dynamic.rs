@@ -0,0 +1,58 @@
+use core::borrow::BorrowMut;
+
+use super::{steps, State};
+
+impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsOptionsDynamic> {
+    /// Add an option.
+    ///
+    /// Returns the state unchanged as an error if the option does not fit into the packet.
+    #[allow(clippy::result_large_err)]
+    pub fn add_option(mut self, option_code: u8, option_data: &[u8]) -> Result<Self, Self> {
+        let Ok(option_length) = u16::try_from(option_data.len()) else {
+            return Err(self);
+        };
+
+        let offset = self.step.next_option_offset;
+        let data_offset = offset + pcp_packet::option::header::LEN;
+        let next_option_offset = data_offset + pcp_packet::padded_len(option_data.len());
+        if next_option_offset > pcp_packet::LEN {
+            return Err(self);
+        }
+
+        {
+            let packet = self.packet.borrow_mut();
+
+            let header: &mut pcp_packet::option::header::Buffer =
+                (&mut packet[offset..data_offset]).try_into().unwrap();
+            let header: &mut pcp_packet::option::header::Data = bytemuck::must_cast_mut(header);
+
+            *header = pcp_packet::option::header::Data {
+                option_code,
+                reserved1: [0; 1],
+                option_length: option_length.to_be_bytes(),
+            };
+
+            packet[data_offset..data_offset + option_data.len()].copy_from_slice(option_data);
+        }
+
+        self.step.next_option_offset = next_option_offset;
+        Ok(self)
+    }
+
+    /// The length of the packet encoded so far.
+    ///
+    /// Only this many bytes of the packet buffer are to be sent.
+    pub fn packet_len(&self) -> usize {
+        self.step.next_option_offset
+    }
+
+    pub fn finish(self) -> Packet {
+        self.packet
+    }
+
+    /// Finish the encoding, also returning the length of the encoded packet.
+    pub fn finish_with_len(self) -> (Packet, usize) {
+        let len = self.packet_len();
+        (self.finish(), len)
+    }
+}
@@ -13,7 +13,10 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsRequestOpc
         request_header: data::request::Header,
         opcode: pcp_primitives::Opcode,
         opcode_data: &[u8; OPCODE_DATA_LEN],
-    ) -> Result<State<Packet, steps::NeedsOptions<OPCODE_DATA_LEN>>, Self> {
+    ) -> Result<
+        State<Packet, steps::NeedsOptions<{ pcp_packet::header::LEN + OPCODE_DATA_LEN }>>,
+        Self,
+    > {
         let Some(r_and_opcode) = pcp_packet::RAndOpcode::from_parts(false, opcode) else {
             return Err(self);
         };
@@ -61,12 +64,24 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsRequestOpc
         })
     }
 
+    #[allow(clippy::result_large_err)]
+    pub fn announce(
+        self,
+        request_header: data::request::Header,
+    ) -> State<Packet, steps::NeedsOptions<{ pcp_packet::header::LEN }>> {
+        self.opcode(request_header, pcp_consts::opcode::ANNOUNCE, &[])
+            .unwrap()
+    }
+
     #[allow(clippy::result_large_err)]
     pub fn map(
         self,
         request_header: data::request::Header,
         request_data: data::request::Map,
-    ) -> State<Packet, steps::NeedsOptions<{ pcp_packet::opcode::map::LEN }>> {
+    ) -> State<
+        Packet,
+        steps::NeedsOptions<{ pcp_packet::header::LEN + pcp_packet::opcode::map::LEN }>,
+    > {
         let data::request::Map {
             mapping_nonce,
             protocol,
@@ -89,4 +104,41 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsRequestOpc
         self.opcode(request_header, opcode, opcode_data_ref)
             .unwrap()
     }
+
+    #[allow(clippy::result_large_err)]
+    pub fn peer(
+        self,
+        request_header: data::request::Header,
+        request_data: data::request::Peer,
+    ) -> State<
+        Packet,
+        steps::NeedsOptions<{ pcp_packet::header::LEN + pcp_packet::opcode::peer::LEN }>,
+    > {
+        let data::request::Peer {
+            mapping_nonce,
+            protocol,
+            internal_port,
+            suggested_external_port,
+            suggested_external_ip_address,
+            remote_peer_port,
+            remote_peer_ip_address,
+        } = request_data;
+
+        let opcode = pcp_consts::opcode::PEER;
+        let opcode_data = pcp_packet::opcode::peer::Request {
+            mapping_nonce,
+            protocol,
+            reserved1: [0; 3],
+            internal_port: internal_port.to_be_bytes(),
+            suggested_external_port: suggested_external_port.to_be_bytes(),
+            suggested_external_ip_address: suggested_external_ip_address.octets(),
+            remote_peer_port: remote_peer_port.to_be_bytes(),
+            reserved2: [0; 2],
+            remote_peer_ip_address: remote_peer_ip_address.octets(),
+        };
+        let opcode_data_ref: &pcp_packet::opcode::peer::Buffer =
+            bytemuck::must_cast_ref(&opcode_data);
+        self.opcode(request_header, opcode, opcode_data_ref)
+            .unwrap()
+    }
 }
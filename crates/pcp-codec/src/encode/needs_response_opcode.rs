@@ -13,7 +13,10 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsResponseOp
         response_header: data::response::Header,
         opcode: pcp_primitives::Opcode,
         opcode_data: &[u8; OPCODE_DATA_LEN],
-    ) -> Result<State<Packet, steps::NeedsOptions<OPCODE_DATA_LEN>>, Self> {
+    ) -> Result<
+        State<Packet, steps::NeedsOptions<{ pcp_packet::header::LEN + OPCODE_DATA_LEN }>>,
+        Self,
+    > {
         let Some(r_and_opcode) = pcp_packet::RAndOpcode::from_parts(true, opcode) else {
             return Err(self);
         };
@@ -64,12 +67,24 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsResponseOp
         })
     }
 
+    #[allow(clippy::result_large_err)]
+    pub fn announce(
+        self,
+        response_header: data::response::Header,
+    ) -> State<Packet, steps::NeedsOptions<{ pcp_packet::header::LEN }>> {
+        self.opcode(response_header, pcp_consts::opcode::ANNOUNCE, &[])
+            .unwrap()
+    }
+
     #[allow(clippy::result_large_err)]
     pub fn map(
         self,
         response_header: data::response::Header,
         response_data: data::response::Map,
-    ) -> State<Packet, steps::NeedsOptions<{ pcp_packet::opcode::map::LEN }>> {
+    ) -> State<
+        Packet,
+        steps::NeedsOptions<{ pcp_packet::header::LEN + pcp_packet::opcode::map::LEN }>,
+    > {
         let data::response::Map {
             mapping_nonce,
             protocol,
@@ -92,4 +107,41 @@ impl<Packet: BorrowMut<pcp_packet::Buffer>> State<Packet, steps::NeedsResponseOp
         self.opcode(response_header, opcode, opcode_data_ref)
             .unwrap()
     }
+
+    #[allow(clippy::result_large_err)]
+    pub fn peer(
+        self,
+        response_header: data::response::Header,
+        response_data: data::response::Peer,
+    ) -> State<
+        Packet,
+        steps::NeedsOptions<{ pcp_packet::header::LEN + pcp_packet::opcode::peer::LEN }>,
+    > {
+        let data::response::Peer {
+            mapping_nonce,
+            protocol,
+            internal_port,
+            assigned_external_port,
+            assigned_external_ip_address,
+            remote_peer_port,
+            remote_peer_ip_address,
+        } = response_data;
+
+        let opcode = pcp_consts::opcode::PEER;
+        let opcode_data = pcp_packet::opcode::peer::Response {
+            mapping_nonce,
+            protocol,
+            reserved1: [0; 3],
+            internal_port: internal_port.to_be_bytes(),
+            assigned_external_port: assigned_external_port.to_be_bytes(),
+            assigned_external_ip_address: assigned_external_ip_address.octets(),
+            remote_peer_port: remote_peer_port.to_be_bytes(),
+            reserved2: [0; 2],
+            remote_peer_ip_address: remote_peer_ip_address.octets(),
+        };
+        let opcode_data_ref: &pcp_packet::opcode::peer::Buffer =
+            bytemuck::must_cast_ref(&opcode_data);
+        self.opcode(response_header, opcode, opcode_data_ref)
+            .unwrap()
+    }
 }
@@ -1,6 +1,12 @@
+extern crate std;
+
 use core::net::{Ipv4Addr, Ipv6Addr};
+use std::vec::Vec;
 
-use crate::{data::request, decode, encode};
+use crate::{
+    data::{option, request, response},
+    decode, encode,
+};
 
 fn assert_packet<const ASSERTION_LEN: usize>(
     packet: pcp_packet::Buffer,
@@ -77,3 +83,346 @@ fn decode() {
         (sample_header, sample_map)
     );
 }
+
+#[test]
+fn encode_peer() {
+    let packet = encode::State::new_owned()
+        .request()
+        .peer(
+            request::Header {
+                requested_lifetime: 60,
+                client_ip_address: Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped(),
+            },
+            request::Peer {
+                mapping_nonce: [
+                    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+                ],
+                protocol: pcp_consts::protocol::UDP,
+                internal_port: 51820,
+                suggested_external_port: 51820,
+                suggested_external_ip_address: Ipv6Addr::UNSPECIFIED,
+                remote_peer_port: 443,
+                remote_peer_ip_address: Ipv4Addr::new(5, 6, 7, 8).to_ipv6_mapped(),
+            },
+        )
+        .finish();
+
+    let expected = [
+        0x02, // version
+        0x02, // r and opcode for PEER request
+        0, 0, // reserved, zeroes
+        0, 0, 0, 60, // lifetime, 60 seconds
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 1, 2, 3, 4, // client IP address
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, // nonce
+        17,   // protocol, UDP
+        0, 0, 0, // reserved, zeroes
+        0xca, 0x6c, // internal port
+        0xca, 0x6c, // suggested external port
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // suggested external IP address
+        0x01, 0xbb, // remote peer port
+        0, 0, // reserved, zeroes
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 5, 6, 7, 8, // remote peer IP address
+    ];
+
+    assert_packet(packet, expected);
+}
+
+#[test]
+fn decode_peer() {
+    let sample_header = response::Header {
+        result_code: pcp_consts::result_code::SUCCESS,
+        lifetime: 120,
+        epoch_time: 1000,
+    };
+
+    let sample_peer = response::Peer {
+        mapping_nonce: [
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+        ],
+        protocol: pcp_consts::protocol::UDP,
+        internal_port: 51820,
+        assigned_external_port: 40000,
+        assigned_external_ip_address: Ipv4Addr::new(203, 0, 113, 1).to_ipv6_mapped(),
+        remote_peer_port: 443,
+        remote_peer_ip_address: Ipv4Addr::new(5, 6, 7, 8).to_ipv6_mapped(),
+    };
+
+    let packet = encode::State::new_owned()
+        .response()
+        .peer(sample_header, sample_peer)
+        .finish();
+
+    let decoder = decode::State::new(&packet);
+
+    assert_eq!(
+        decoder.peer_response_data().unwrap(),
+        (sample_header, sample_peer)
+    );
+    assert_eq!(decoder.map_response_data(), None);
+    assert_eq!(decoder.peer_request_data(), None);
+}
+
+#[test]
+fn announce() {
+    let sample_header = response::Header {
+        result_code: pcp_consts::result_code::SUCCESS,
+        lifetime: 0,
+        epoch_time: 0,
+    };
+
+    let packet = encode::State::new_owned()
+        .response()
+        .announce(sample_header)
+        .finish();
+
+    let expected = [
+        0x02, // version
+        0x80, // r and opcode for ANNOUNCE response
+        0, 0, // reserved, result code SUCCESS
+        0, 0, 0, 0, // lifetime, zero
+        0, 0, 0, 0, // epoch time, zero
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // reserved, zeroes
+    ];
+
+    assert_packet(packet, expected);
+
+    let decoder = decode::State::new(&packet);
+
+    assert_eq!(decoder.announce_response_data(), Some(sample_header));
+    assert_eq!(decoder.announce_request_data(), None);
+    assert_eq!(decoder.map_response_data(), None);
+}
+
+#[test]
+fn encode_options() {
+    let (packet, len) = encode::State::new_owned()
+        .request()
+        .map(
+            request::Header {
+                requested_lifetime: 60,
+                client_ip_address: Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped(),
+            },
+            request::Map {
+                mapping_nonce: [
+                    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+                ],
+                protocol: pcp_consts::protocol::TCP,
+                internal_port: 80,
+                suggested_external_port: 80,
+                suggested_external_ip_address: Ipv6Addr::UNSPECIFIED,
+            },
+        )
+        .add_option(pcp_consts::option::PREFER_FAILURE, &[])
+        .add_option(0x80, &[0xAA, 0xBB])
+        .finish_with_len();
+
+    let expected = [
+        0x02, // version
+        0x01, // r and opcode for MAP request
+        0, 0, // reserved, zeroes
+        0, 0, 0, 60, // lifetime, 60 seconds
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 1, 2, 3, 4, // client IP address
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, // nonce
+        6,    // protocol, TCP
+        0, 0, 0, // reserved, zeroes
+        0, 80, // internal port
+        0, 80, // suggested external port
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // suggested external IP address
+        2, 0, 0, 0, // PREFER_FAILURE option, no data
+        0x80, 0, 0, 2, // optional option with two bytes of data
+        0xAA, 0xBB, 0, 0, // option data, padded
+    ];
+
+    assert_eq!(len, expected.len());
+    assert_packet(packet, expected);
+}
+
+#[test]
+fn decode_short() {
+    let sample_header = response::Header {
+        result_code: pcp_consts::result_code::SUCCESS,
+        lifetime: 120,
+        epoch_time: 1000,
+    };
+
+    let sample_map = response::Map {
+        mapping_nonce: [
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+        ],
+        protocol: pcp_consts::protocol::TCP,
+        internal_port: 80,
+        assigned_external_port: 8080,
+        assigned_external_ip_address: Ipv4Addr::new(203, 0, 113, 1).to_ipv6_mapped(),
+    };
+
+    let (packet, len) = encode::State::new_owned()
+        .response()
+        .map(sample_header, sample_map)
+        .finish_with_len();
+    assert_eq!(len, 60);
+
+    let decoder = decode::State::with_len(&packet, len);
+    assert_eq!(
+        decoder.map_response_data(),
+        Some((sample_header, sample_map))
+    );
+
+    // Truncated opcode data.
+    let decoder = decode::State::with_len(&packet, len - 4);
+    assert_eq!(decoder.map_response_data(), None);
+
+    // Truncated header.
+    let decoder = decode::State::with_len(&packet, 20);
+    assert_eq!(decoder.announce_response_data(), None);
+}
+
+fn map_request_with_options<const LEN: usize>(
+    add_options: impl FnOnce(
+        encode::State<pcp_packet::Buffer, encode::steps::NeedsOptions<60>>,
+    ) -> encode::State<pcp_packet::Buffer, encode::steps::NeedsOptions<LEN>>,
+) -> (pcp_packet::Buffer, usize) {
+    let enc = encode::State::new_owned().request().map(
+        request::Header {
+            requested_lifetime: 60,
+            client_ip_address: Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped(),
+        },
+        request::Map {
+            mapping_nonce: [0; 12],
+            protocol: pcp_consts::protocol::TCP,
+            internal_port: 80,
+            suggested_external_port: 80,
+            suggested_external_ip_address: Ipv6Addr::UNSPECIFIED,
+        },
+    );
+    add_options(enc).finish_with_len()
+}
+
+#[test]
+fn decode_options() {
+    let third_party = Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped();
+    let filter = option::Filter {
+        prefix_length: 120,
+        remote_peer_port: 443,
+        remote_peer_ip_address: Ipv4Addr::new(5, 6, 7, 0).to_ipv6_mapped(),
+    };
+
+    let mut filter_data = [0; 20];
+    filter_data[1] = filter.prefix_length;
+    filter_data[2..4].copy_from_slice(&filter.remote_peer_port.to_be_bytes());
+    filter_data[4..].copy_from_slice(&filter.remote_peer_ip_address.octets());
+
+    let (packet, len) = map_request_with_options(|enc| {
+        enc.add_option(pcp_consts::option::THIRD_PARTY, &third_party.octets())
+            .add_option(pcp_consts::option::PREFER_FAILURE, &[])
+            .add_option(pcp_consts::option::FILTER, &filter_data)
+            .add_option(0x80, &[0xAA, 0xBB])
+    });
+
+    let decoder = decode::State::with_len(&packet, len);
+    let options: Vec<_> = decoder.options().collect();
+
+    assert_eq!(
+        options,
+        [
+            Ok(decode::options::Decoded {
+                code: pcp_consts::option::THIRD_PARTY,
+                value: decode::options::Value::ThirdParty(option::ThirdParty {
+                    internal_ip_address: third_party,
+                }),
+            }),
+            Ok(decode::options::Decoded {
+                code: pcp_consts::option::PREFER_FAILURE,
+                value: decode::options::Value::PreferFailure,
+            }),
+            Ok(decode::options::Decoded {
+                code: pcp_consts::option::FILTER,
+                value: decode::options::Value::Filter(filter),
+            }),
+            Ok(decode::options::Decoded {
+                code: 0x80,
+                value: decode::options::Value::Unknown(&[0xAA, 0xBB]),
+            }),
+        ]
+    );
+}
+
+#[test]
+fn decode_options_errors() {
+    let (packet, len) = map_request_with_options(|enc| enc.add_option(0x7F, &[]));
+    let decoder = decode::State::with_len(&packet, len);
+    assert_eq!(
+        decoder.options().collect::<Vec<_>>(),
+        [Err(decode::options::Error::UnknownMandatory { code: 0x7F })]
+    );
+
+    let (packet, len) =
+        map_request_with_options(|enc| enc.add_option(pcp_consts::option::PREFER_FAILURE, &[1]));
+    let decoder = decode::State::with_len(&packet, len);
+    assert_eq!(
+        decoder.options().collect::<Vec<_>>(),
+        [Err(decode::options::Error::InvalidLength {
+            code: pcp_consts::option::PREFER_FAILURE,
+            length: 1,
+        })]
+    );
+
+    let (packet, len) = map_request_with_options(|enc| {
+        enc.add_option(pcp_consts::option::PREFER_FAILURE, &[])
+            .add_option(0x80, &[1, 2, 3, 4, 5, 6, 7, 8])
+    });
+    let decoder = decode::State::with_len(&packet, len - 4);
+    assert_eq!(
+        decoder.options().collect::<Vec<_>>(),
+        [
+            Ok(decode::options::Decoded {
+                code: pcp_consts::option::PREFER_FAILURE,
+                value: decode::options::Value::PreferFailure,
+            }),
+            Err(decode::options::Error::Truncated { offset: 64 }),
+        ]
+    );
+}
+
+#[test]
+fn encode_options_dynamic() {
+    let typed = map_request_with_options(|enc| {
+        enc.add_option(pcp_consts::option::PREFER_FAILURE, &[])
+            .add_option(0x80, &[0xAA, 0xBB])
+    });
+
+    let (packet, len) = encode::State::new_owned()
+        .request()
+        .map(
+            request::Header {
+                requested_lifetime: 60,
+                client_ip_address: Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped(),
+            },
+            request::Map {
+                mapping_nonce: [0; 12],
+                protocol: pcp_consts::protocol::TCP,
+                internal_port: 80,
+                suggested_external_port: 80,
+                suggested_external_ip_address: Ipv6Addr::UNSPECIFIED,
+            },
+        )
+        .dynamic()
+        .add_option(pcp_consts::option::PREFER_FAILURE, &[])
+        .unwrap()
+        .add_option(0x80, &[0xAA, 0xBB])
+        .unwrap()
+        .finish_with_len();
+
+    assert_eq!((packet, len), typed);
+
+    // The options that do not fit are rejected.
+    let enc = encode::State::new_owned()
+        .request()
+        .announce(request::Header {
+            requested_lifetime: 0,
+            client_ip_address: Ipv6Addr::UNSPECIFIED,
+        })
+        .dynamic();
+    let enc = enc.add_option(0x80, &[0; pcp_packet::LEN - 28]).unwrap();
+    assert_eq!(enc.packet_len(), pcp_packet::LEN);
+    assert!(enc.add_option(0x80, &[]).is_err());
+}
@@ -74,4 +74,11 @@ pub mod option {
     pub const THIRD_PARTY: OptionCode = 1;
     pub const PREFER_FAILURE: OptionCode = 2;
     pub const FILTER: OptionCode = 3;
+
+    /// Check if the option is in the mandatory-to-process range.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/rfc6887#section-7.3>.
+    pub const fn is_mandatory(option_code: OptionCode) -> bool {
+        option_code & 0b1000_0000 == 0
+    }
 }
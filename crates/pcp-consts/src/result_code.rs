@@ -77,3 +77,26 @@ pub const ADDRESS_MISMATCH: ResultCode = 12;
 /// if the MAP request contained the FILTER option.  See Section 13.3
 /// for details of the FILTER Option.  This is a long lifetime error.
 pub const EXCESSIVE_REMOTE_PEERS: ResultCode = 13;
+
+/// The name of the result code, as it is spelled in the RFC.
+///
+/// Returns `None` for the result codes we do not recognize.
+pub fn name(result_code: ResultCode) -> Option<&'static str> {
+    Some(match result_code {
+        SUCCESS => "SUCCESS",
+        UNSUPP_VERSION => "UNSUPP_VERSION",
+        NOT_AUTHORIZED => "NOT_AUTHORIZED",
+        MALFORMED_REQUEST => "MALFORMED_REQUEST",
+        UNSUPP_OPCODE => "UNSUPP_OPCODE",
+        UNSUPP_OPTION => "UNSUPP_OPTION",
+        MALFORMED_OPTION => "MALFORMED_OPTION",
+        NETWORK_FAILURE => "NETWORK_FAILURE",
+        NO_RESOURCES => "NO_RESOURCES",
+        UNSUPP_PROTOCOL => "UNSUPP_PROTOCOL",
+        USER_EX_QUOTA => "USER_EX_QUOTA",
+        CANNOT_PROVIDE_EXTERNAL => "CANNOT_PROVIDE_EXTERNAL",
+        ADDRESS_MISMATCH => "ADDRESS_MISMATCH",
+        EXCESSIVE_REMOTE_PEERS => "EXCESSIVE_REMOTE_PEERS",
+        _ => return None,
+    })
+}
@@ -1,5 +1,16 @@
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 
+pub mod result;
+
+use std::time::{Duration, Instant};
+
+use pcp_consts::primitives::ResultCode;
+
+/// The minimal time to hold off re-sending after an error response.
+///
+/// Guards against the servers reporting zero error lifetimes.
+pub const MIN_ERROR_LIFETIME: Duration = Duration::from_secs(1);
+
 pub trait Mapping {
     /// Return true if this is the same mapping instance.
     ///
@@ -21,6 +32,15 @@ pub trait Incoming<Other> {
     fn is_same_exposed_resource(&self, other: &Other) -> bool;
 }
 
+pub trait Response {
+    /// The result code of the response.
+    fn result_code(&self) -> ResultCode;
+
+    /// The lifetime the server has granted, or the error lifetime if the response
+    /// is an error.
+    fn lifetime(&self) -> Duration;
+}
+
 pub mod cleanup {
     pub trait Maybe {
         fn is_cleanup(&self) -> bool;
@@ -38,7 +58,7 @@ pub struct State<RenewMapping, CleanupMapping, IncomingMapping>
 where
     RenewMapping: Mapping + cleanup::Into<Mapping = CleanupMapping>,
     CleanupMapping: Mapping,
-    IncomingMapping: cleanup::Maybe + Incoming<RenewMapping> + Incoming<CleanupMapping>,
+    IncomingMapping: cleanup::Maybe + Response + Incoming<RenewMapping> + Incoming<CleanupMapping>,
 {
     /// The desired state.
     ///
@@ -48,8 +68,29 @@ where
     /// The effective state that is send to and reported by the server.
     effective: Option<IncomingMapping>,
 
+    /// Whether the effective state was granted for the current desired state.
+    ///
+    /// Cleared once the desired state changes, as the prior grant no longer reflects it.
+    effective_is_current: bool,
+
     /// The cleanup queue.
     cleanup_queue: Vec<CleanupMapping>,
+
+    /// The lifetime granted by the server for the effective state.
+    granted_lifetime: Option<Duration>,
+
+    /// The time at which the desired state is to be renewed.
+    ///
+    /// `None` means the desired state is to be sent right away.
+    renew_at: Option<Instant>,
+
+    /// The last error the server has responded with.
+    ///
+    /// Cleared once the server responds with a success.
+    last_error: Option<result::Error>,
+
+    /// The time until which nothing is to be sent due to an error response.
+    suppressed_until: Option<Instant>,
 }
 
 #[derive(Debug)]
@@ -63,7 +104,7 @@ impl<RenewMapping, CleanupMapping, IncomingMapping>
 where
     RenewMapping: Mapping + cleanup::Into<Mapping = CleanupMapping>,
     CleanupMapping: Mapping,
-    IncomingMapping: cleanup::Maybe + Incoming<RenewMapping> + Incoming<CleanupMapping>,
+    IncomingMapping: cleanup::Maybe + Response + Incoming<RenewMapping> + Incoming<CleanupMapping>,
 {
     pub fn new(mapping: RenewMapping) -> Self {
         let desired = Some(mapping);
@@ -72,18 +113,35 @@ where
         Self {
             desired,
             effective,
+            effective_is_current: false,
             cleanup_queue,
+            granted_lifetime: None,
+            renew_at: None,
+            last_error: None,
+            suppressed_until: None,
         }
     }
 
-    pub fn update_desired(&mut self, new_mapping: RenewMapping) -> UpdateDesiredOutcome {
+    pub fn update_desired(&mut self, new_mapping: RenewMapping) -> UpdateDesiredOutcome
+    where
+        RenewMapping: PartialEq,
+    {
         match self.desired {
             None => {
                 self.desired = Some(new_mapping);
+                self.renew_at = None;
+                self.effective_is_current = false;
                 UpdateDesiredOutcome::InPlace
             }
+            Some(ref desired_mapping) if desired_mapping == &new_mapping => {
+                UpdateDesiredOutcome::Unchanged
+            }
             Some(ref mut desired_mapping) => {
                 let old_mapping = core::mem::replace(desired_mapping, new_mapping);
+                self.renew_at = None;
+                self.effective_is_current = false;
+                // The request has changed, so the prior error might no longer apply.
+                self.suppressed_until = None;
                 if old_mapping.is_same_mapping_instance(desired_mapping) {
                     UpdateDesiredOutcome::InPlace
                 } else {
@@ -106,14 +164,45 @@ where
         }
     }
 
-    pub fn pending_actions(&self) -> PendingActions<'_, RenewMapping, CleanupMapping> {
+    /// The actions that are due at the given time.
+    ///
+    /// The renewal is only due once the [`Self::renew_at`] time is reached, and nothing
+    /// is due while the re-sending is suppressed after an error response.
+    pub fn pending_actions(
+        &self,
+        now: Instant,
+    ) -> PendingActions<'_, RenewMapping, CleanupMapping> {
+        if self.suppressed_until.is_some_and(|until| until > now) {
+            return PendingActions {
+                renew: None,
+                cleanup: &[],
+            };
+        }
+
+        let renew_due = self.renew_at.map_or(true, |renew_at| renew_at <= now);
         PendingActions {
-            renew: self.desired.as_ref(),
+            renew: self.desired.as_ref().filter(|_| renew_due),
             cleanup: self.cleanup_queue.as_slice(),
         }
     }
 
-    pub fn handle_server_notification(&mut self, incoming: IncomingMapping) {
+    /// Handle the response from the server.
+    ///
+    /// The `rand` is a random value in the `[0, 1]` range used to pick the renewal time
+    /// between 1/2 and 5/8 of the granted lifetime.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/rfc6887#section-11.2.1>.
+    pub fn handle_server_notification(
+        &mut self,
+        incoming: IncomingMapping,
+        now: Instant,
+        rand: f64,
+    ) {
+        if let result::Class::Error(kind) = result::classify(incoming.result_code()) {
+            self.handle_error_response(incoming, kind, now);
+            return;
+        }
+
         if incoming.is_cleanup() {
             self.cleanup_queue
                 .retain(|cleanup| !incoming.is_same_exposed_resource(cleanup));
@@ -121,13 +210,69 @@ where
 
         if let Some(desired) = &self.desired {
             if incoming.is_same_exposed_resource(desired) {
+                let lifetime = incoming.lifetime();
+                self.granted_lifetime = Some(lifetime);
+                self.renew_at = Some(now + renewal_delay(lifetime, rand));
+                self.last_error = None;
+                self.suppressed_until = None;
                 self.effective = Some(incoming);
+                self.effective_is_current = true;
             }
         }
 
         if self.cleanup_queue.is_empty() && self.desired.is_none() {
             self.effective = None;
+            self.effective_is_current = false;
+            self.granted_lifetime = None;
+            self.last_error = None;
+            self.suppressed_until = None;
+        }
+    }
+
+    /// Record the error response and hold off re-sending for the error lifetime.
+    fn handle_error_response(
+        &mut self,
+        incoming: IncomingMapping,
+        kind: result::ErrorKind,
+        now: Instant,
+    ) {
+        let is_relevant = self
+            .desired
+            .as_ref()
+            .is_some_and(|desired| incoming.is_same_exposed_resource(desired))
+            || self
+                .cleanup_queue
+                .iter()
+                .any(|cleanup| incoming.is_same_exposed_resource(cleanup));
+        if !is_relevant {
+            return;
         }
+
+        let lifetime = incoming.lifetime();
+        self.last_error = Some(result::Error {
+            result_code: incoming.result_code(),
+            kind,
+            lifetime,
+        });
+        self.suppressed_until = Some(now + lifetime.max(MIN_ERROR_LIFETIME));
+    }
+
+    /// Hold off sending anything until the given time.
+    pub fn postpone(&mut self, until: Instant) {
+        self.suppressed_until = Some(until);
+    }
+
+    /// Make the renewal of the desired state due right away.
+    ///
+    /// Useful when the server is known to have lost its state.
+    pub fn expedite_renewal(&mut self) {
+        self.renew_at = None;
+        self.suppressed_until = None;
+    }
+
+    /// Check if there is nothing left to manage in this state.
+    pub fn is_idle(&self) -> bool {
+        self.desired.is_none() && self.cleanup_queue.is_empty()
     }
 
     pub fn desired(&self) -> Option<&RenewMapping> {
@@ -137,10 +282,67 @@ where
     pub fn effective(&self) -> Option<&IncomingMapping> {
         self.effective.as_ref()
     }
+
+    /// The effective state to suggest to the server when renewing the desired state.
+    ///
+    /// Only present if it was granted for the current desired state, so that the changes
+    /// to the desired state are not overridden by the prior grant.
+    ///
+    /// See <https://datatracker.ietf.org/doc/html/rfc6887#section-11.2>.
+    pub fn suggestion(&self) -> Option<&IncomingMapping> {
+        self.effective
+            .as_ref()
+            .filter(|_| self.effective_is_current)
+    }
+
+    pub fn granted_lifetime(&self) -> Option<Duration> {
+        self.granted_lifetime
+    }
+
+    /// The time at which the desired state is scheduled to be renewed.
+    ///
+    /// `None` if there is no desired state, or it is to be sent right away.
+    pub fn renew_at(&self) -> Option<Instant> {
+        self.desired.as_ref().and(self.renew_at)
+    }
+
+    /// The last error the server has responded with, if it has not succeeded since.
+    pub fn last_error(&self) -> Option<&result::Error> {
+        self.last_error.as_ref()
+    }
+
+    /// The time at which the state is going to have pending actions.
+    ///
+    /// `None` if there is nothing to manage, or the actions are pending right away.
+    pub fn next_action_at(&self) -> Option<Instant> {
+        if self.is_idle() {
+            return None;
+        }
+
+        let renew_at = self.renew_at();
+        match self.suppressed_until {
+            Some(until) => Some(renew_at.map_or(until, |renew_at| renew_at.max(until))),
+            None => renew_at,
+        }
+    }
+}
+
+/// The time after which the mapping with the given lifetime is to be renewed.
+///
+/// The `rand` is a random value in the `[0, 1]` range used to pick the point
+/// between 1/2 and 5/8 of the lifetime.
+pub fn renewal_delay(lifetime: Duration, rand: f64) -> Duration {
+    lifetime.mul_f64(0.5 + 0.125 * rand.clamp(0.0, 1.0))
 }
 
 /// The outcome of the [`State::update_desired`] call.
 pub enum UpdateDesiredOutcome {
+    /// The `desired` value was the same as the new one, so no state changes
+    /// actually occurred.
+    ///
+    /// The state does not need to be reconciled.
+    Unchanged,
+
     /// The `desired` value was updated in-place.
     ///
     /// The state might not need to be reconciled.
@@ -165,3 +367,249 @@ pub enum RemoveDesiredOutcome {
     /// The state might not need to be reconciled.
     WasAbsent,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct TestMapping {
+        port: u16,
+        lifetime: u32,
+    }
+
+    #[derive(Debug)]
+    struct TestIncoming {
+        port: u16,
+        result_code: ResultCode,
+        lifetime: u32,
+    }
+
+    impl Mapping for TestMapping {
+        fn is_same_mapping_instance(&self, other: &Self) -> bool {
+            self.port == other.port
+        }
+    }
+
+    impl Incoming<TestMapping> for TestIncoming {
+        fn is_same_exposed_resource(&self, other: &TestMapping) -> bool {
+            self.port == other.port
+        }
+    }
+
+    impl Response for TestIncoming {
+        fn result_code(&self) -> ResultCode {
+            self.result_code
+        }
+
+        fn lifetime(&self) -> Duration {
+            Duration::from_secs(self.lifetime.into())
+        }
+    }
+
+    impl cleanup::Maybe for TestIncoming {
+        fn is_cleanup(&self) -> bool {
+            self.result_code == pcp_consts::result_code::SUCCESS && self.lifetime == 0
+        }
+    }
+
+    impl cleanup::Into for TestMapping {
+        type Mapping = Self;
+
+        fn into_cleanup(self) -> Self::Mapping {
+            Self {
+                lifetime: 0,
+                ..self
+            }
+        }
+    }
+
+    type TestState = State<TestMapping, TestMapping, TestIncoming>;
+
+    #[test]
+    fn renewal_schedule() {
+        let now = Instant::now();
+        let mut state = TestState::new(TestMapping {
+            port: 80,
+            lifetime: 3600,
+        });
+
+        assert!(state.pending_actions(now).renew.is_some());
+        assert_eq!(state.renew_at(), None);
+
+        // The server shortens the lifetime.
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::SUCCESS,
+                lifetime: 800,
+            },
+            now,
+            0.0,
+        );
+        assert_eq!(state.granted_lifetime(), Some(Duration::from_secs(800)));
+        assert_eq!(state.renew_at(), Some(now + Duration::from_secs(400)));
+
+        assert!(state
+            .pending_actions(now + Duration::from_secs(399))
+            .renew
+            .is_none());
+        assert!(state
+            .pending_actions(now + Duration::from_secs(400))
+            .renew
+            .is_some());
+
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::SUCCESS,
+                lifetime: 800,
+            },
+            now,
+            1.0,
+        );
+        assert_eq!(state.renew_at(), Some(now + Duration::from_secs(500)));
+
+        state.expedite_renewal();
+        assert!(state.pending_actions(now).renew.is_some());
+    }
+
+    #[test]
+    fn update_desired_reschedules() {
+        let now = Instant::now();
+        let mut state = TestState::new(TestMapping {
+            port: 80,
+            lifetime: 3600,
+        });
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::SUCCESS,
+                lifetime: 3600,
+            },
+            now,
+            0.0,
+        );
+
+        assert!(matches!(
+            state.update_desired(TestMapping {
+                port: 80,
+                lifetime: 3600,
+            }),
+            UpdateDesiredOutcome::Unchanged
+        ));
+        assert!(state.pending_actions(now).renew.is_none());
+
+        assert!(matches!(
+            state.update_desired(TestMapping {
+                port: 80,
+                lifetime: 7200,
+            }),
+            UpdateDesiredOutcome::InPlace
+        ));
+        assert!(state.pending_actions(now).renew.is_some());
+    }
+
+    #[test]
+    fn suggestion() {
+        let now = Instant::now();
+        let mut state = TestState::new(TestMapping {
+            port: 80,
+            lifetime: 3600,
+        });
+        assert!(state.suggestion().is_none());
+
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::SUCCESS,
+                lifetime: 3600,
+            },
+            now,
+            0.0,
+        );
+        assert!(state.suggestion().is_some());
+
+        // The prior grant does not reflect the new desired state.
+        state.update_desired(TestMapping {
+            port: 80,
+            lifetime: 7200,
+        });
+        assert!(state.suggestion().is_none());
+        assert!(state.effective().is_some());
+    }
+
+    #[test]
+    fn error_suppression() {
+        let now = Instant::now();
+        let mut state = TestState::new(TestMapping {
+            port: 80,
+            lifetime: 3600,
+        });
+
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::NOT_AUTHORIZED,
+                lifetime: 1800,
+            },
+            now,
+            0.0,
+        );
+        assert!(state.effective().is_none());
+        assert_eq!(
+            state.last_error(),
+            Some(&result::Error {
+                result_code: pcp_consts::result_code::NOT_AUTHORIZED,
+                kind: result::ErrorKind::LongLifetime,
+                lifetime: Duration::from_secs(1800),
+            })
+        );
+        assert_eq!(
+            state.next_action_at(),
+            Some(now + Duration::from_secs(1800))
+        );
+        assert!(state
+            .pending_actions(now + Duration::from_secs(1799))
+            .renew
+            .is_none());
+        assert!(state
+            .pending_actions(now + Duration::from_secs(1800))
+            .renew
+            .is_some());
+
+        state.handle_server_notification(
+            TestIncoming {
+                port: 80,
+                result_code: pcp_consts::result_code::SUCCESS,
+                lifetime: 3600,
+            },
+            now,
+            0.0,
+        );
+        assert!(state.last_error().is_none());
+        assert!(state.effective().is_some());
+    }
+
+    #[test]
+    fn classify() {
+        use pcp_consts::result_code;
+
+        assert_eq!(
+            result::classify(result_code::SUCCESS),
+            result::Class::Success
+        );
+        assert_eq!(
+            result::classify(result_code::NO_RESOURCES),
+            result::Class::Error(result::ErrorKind::ShortLifetime)
+        );
+        assert_eq!(
+            result::classify(result_code::UNSUPP_PROTOCOL),
+            result::Class::Error(result::ErrorKind::LongLifetime)
+        );
+        assert_eq!(
+            result::classify(200),
+            result::Class::Error(result::ErrorKind::Unknown)
+        );
+    }
+}
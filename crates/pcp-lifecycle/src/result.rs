@@ -0,0 +1,73 @@
+//! Result code classification.
+//!
+//! See <https://datatracker.ietf.org/doc/html/rfc6887#section-7.4>.
+
+use std::time::Duration;
+
+use pcp_consts::{primitives::ResultCode, result_code};
+
+/// The class of the server response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// The request succeeded.
+    Success,
+
+    /// The request failed.
+    Error(ErrorKind),
+}
+
+/// The kind of the error the server responded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The condition is likely to resolve soon, for instance once the server
+    /// has more resources available.
+    ShortLifetime,
+
+    /// The condition is unlikely to resolve without changes on either side,
+    /// for instance the server configuration or the request itself.
+    LongLifetime,
+
+    /// The result code is not known to us, so the lifetime of the condition is unknown.
+    ///
+    /// Held off for the error lifetime just like the other errors.
+    Unknown,
+}
+
+/// The error the server has responded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    /// The result code of the response.
+    pub result_code: ResultCode,
+
+    /// The kind of the error.
+    pub kind: ErrorKind,
+
+    /// The error lifetime the server has reported, during which the request
+    /// is not to be re-sent.
+    pub lifetime: Duration,
+}
+
+/// Classify the result code.
+pub fn classify(result_code: ResultCode) -> Class {
+    let kind = match result_code {
+        result_code::SUCCESS => return Class::Success,
+        result_code::NETWORK_FAILURE
+        | result_code::NO_RESOURCES
+        | result_code::USER_EX_QUOTA
+        // The lifetime of this one depends on the reason of the failure, and the most
+        // common one - the suggested port being taken - is transient.
+        | result_code::CANNOT_PROVIDE_EXTERNAL => ErrorKind::ShortLifetime,
+        result_code::UNSUPP_VERSION
+        | result_code::NOT_AUTHORIZED
+        | result_code::MALFORMED_REQUEST
+        | result_code::UNSUPP_OPCODE
+        | result_code::UNSUPP_OPTION
+        | result_code::MALFORMED_OPTION
+        | result_code::UNSUPP_PROTOCOL
+        | result_code::ADDRESS_MISMATCH
+        | result_code::EXCESSIVE_REMOTE_PEERS => ErrorKind::LongLifetime,
+        // Likely from a newer protocol extension, reported as such rather than guessed.
+        _ => ErrorKind::Unknown,
+    };
+    Class::Error(kind)
+}
@@ -9,11 +9,29 @@ pub mod opcode;
 pub mod option;
 mod r_and_opcode;
 
+/// The maximum length of a PCP packet.
 pub const LEN: usize = 1100;
 
+/// The minimum length of a PCP packet, which is just the header.
+pub const MIN_LEN: usize = header::LEN;
+
 pub type Buffer = [u8; LEN];
 
 pub use meta::Meta;
 pub use r_and_opcode::RAndOpcode;
 
 const ROW_SIZE: usize = 4;
+
+/// Check if the packet of the given length is valid.
+///
+/// PCP packets are variable-length, but always padded to a multiple of 4 bytes.
+///
+/// See <https://datatracker.ietf.org/doc/html/rfc6887#section-7>.
+pub const fn is_valid_len(len: usize) -> bool {
+    len >= MIN_LEN && len <= LEN && len % ROW_SIZE == 0
+}
+
+/// The length of the data once padded to a multiple of 4 bytes.
+pub const fn padded_len(len: usize) -> usize {
+    len.div_ceil(ROW_SIZE) * ROW_SIZE
+}
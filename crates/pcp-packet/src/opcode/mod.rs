@@ -1,3 +1,4 @@
 //! Opcode data.
 
 pub mod map;
+pub mod peer;
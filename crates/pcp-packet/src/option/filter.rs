@@ -0,0 +1,31 @@
+//! `FILTER` option data.
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |    Reserved   | Prefix Length |      Remote Peer Port         | 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                                                               | 2
+// |               Remote Peer IP address (128 bits)               | 3
+// |                                                               | 4
+// |                                                               | 5
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+
+static_assertions::assert_eq_size!(Data, Buffer);
+static_assertions::assert_eq_align!(Data, Buffer);
+
+/// The length in bytes.
+pub const LEN: usize = crate::ROW_SIZE * 5;
+
+/// The buffer of the size to fit the data.
+pub type Buffer = [u8; LEN];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+#[repr(C, packed)]
+pub struct Data {
+    pub reserved1: [u8; 1],
+    pub prefix_length: u8,
+    pub remote_peer_port: [u8; 2],
+    pub remote_peer_ip_address: [u8; 16],
+}
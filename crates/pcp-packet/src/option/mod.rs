@@ -1,4 +1,6 @@
+pub mod filter;
 pub mod header;
+pub mod third_party;
 
 #[repr(C, packed)]
 pub struct Options(pub [u8]);
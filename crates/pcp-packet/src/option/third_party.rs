@@ -0,0 +1,26 @@
+//! `THIRD_PARTY` option data.
+
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                                                               | 1
+// |                Internal IP Address (128 bits)                 | 2
+// |                                                               | 3
+// |                                                               | 4
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+
+static_assertions::assert_eq_size!(Data, Buffer);
+static_assertions::assert_eq_align!(Data, Buffer);
+
+/// The length in bytes.
+pub const LEN: usize = crate::ROW_SIZE * 4;
+
+/// The buffer of the size to fit the data.
+pub type Buffer = [u8; LEN];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+#[repr(C, packed)]
+pub struct Data {
+    pub internal_ip_address: [u8; 16],
+}
@@ -11,6 +11,8 @@ pub enum GatewayForError {
 }
 
 /// Find default gateway for a given interface.
+///
+/// The unspecified address matches any interface of the same IP version.
 pub async fn gateway_for(
     interface: std::net::IpAddr,
 ) -> Result<Option<std::net::IpAddr>, GatewayForError> {
@@ -81,6 +83,9 @@ fn try_route(
 
     let source_prefix = route.header.source_prefix_length;
     let source_matched = match (maybe_source, interface) {
+        // Allow any source if the interface is not specified.
+        (Some(_), interface) if interface.is_unspecified() => true,
+
         (
             Some(netlink_packet_route::route::RouteAddress::Inet(source)),
             std::net::IpAddr::V4(interface),
@@ -165,8 +170,19 @@ mod tests {
         ];
 
         assert_eq!(
-            try_route(std::net::Ipv4Addr::new(192, 168, 0, 10).into(), sample),
+            try_route(
+                std::net::Ipv4Addr::new(192, 168, 0, 10).into(),
+                sample.clone()
+            ),
+            Some(std::net::Ipv4Addr::new(192, 168, 0, 1).into())
+        );
+        assert_eq!(
+            try_route(std::net::Ipv4Addr::UNSPECIFIED.into(), sample.clone()),
             Some(std::net::Ipv4Addr::new(192, 168, 0, 1).into())
         );
+        assert_eq!(
+            try_route(std::net::Ipv4Addr::new(192, 168, 0, 20).into(), sample),
+            None
+        );
     }
 }